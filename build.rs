@@ -15,7 +15,23 @@ fn generate_gl_bindings() {
     .unwrap();
 }
 
+// Only what the surfaceless backend needs to create a context without a display server.
+fn generate_egl_bindings() {
+    let dest = std::path::PathBuf::from(&"bindings");
+    let mut file = std::fs::File::create(&dest.join("egl_bindings.rs")).unwrap();
+    gl_generator::Registry::new(
+        gl_generator::Api::Egl,
+        (1, 5),
+        gl_generator::Profile::Core,
+        gl_generator::Fallbacks::All,
+        ["EGL_MESA_platform_surfaceless", "EGL_KHR_create_context"],
+    )
+    .write_bindings(gl_generator::StructGenerator, &mut file)
+    .unwrap();
+}
+
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
     generate_gl_bindings();
+    generate_egl_bindings();
 }
\ No newline at end of file
@@ -0,0 +1,331 @@
+use crate::{
+    error::{Error, Result},
+    rendergl::view_state::ViewState,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// What happens when playback reaches the last frame.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaybackMode {
+    /// Start over from the first frame.
+    Loop,
+    /// Play backwards to the first frame, then forwards again.
+    Bounce,
+    /// Stop at the last frame.
+    Once,
+}
+
+impl Default for PlaybackMode {
+    fn default() -> Self {
+        PlaybackMode::Loop
+    }
+}
+
+/// The frame to show at a point in time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CineTick {
+    pub frame: usize,
+    /// Frames skipped since the previous tick, because rendering did not keep up.
+    pub dropped: u64,
+}
+
+/// Plays the frames of a multi-frame image at a fixed rate.
+/// The frame is computed from the clock rather than counted per render, so playback
+/// keeps its speed when rendering is slow and frames are dropped instead.
+/// Times are running times of the pipeline clock.
+#[derive(Debug, Clone)]
+pub struct CineController {
+    frame_count: usize,
+    frames_per_second: f64,
+    mode: PlaybackMode,
+    // Position along the unfolded timeline (bounces and loops counted as further frames),
+    // as of `anchor_time`.
+    anchor_position: f64,
+    // Set while playing.
+    anchor_time: Option<Duration>,
+    // Unfolded frame of the last tick, to count dropped frames.
+    last_position: Option<u64>,
+    dropped: u64,
+}
+
+impl CineController {
+    pub fn new(frame_count: usize, frames_per_second: f64) -> Result<Self> {
+        if frame_count == 0 {
+            return Err(Error::InvalidCine("A cine needs at least one frame".into()));
+        }
+        Self::check_rate(frames_per_second)?;
+        Ok(Self {
+            frame_count,
+            frames_per_second,
+            mode: PlaybackMode::default(),
+            anchor_position: 0.0,
+            anchor_time: None,
+            last_position: None,
+            dropped: 0,
+        })
+    }
+
+    fn check_rate(frames_per_second: f64) -> Result<()> {
+        if frames_per_second.is_finite() && frames_per_second > 0.0 {
+            Ok(())
+        } else {
+            Err(Error::InvalidCine(format!(
+                "The frame rate must be positive, got {}",
+                frames_per_second
+            )))
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    pub fn mode(&self) -> PlaybackMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, now: Duration, mode: PlaybackMode) {
+        // Keep showing the same frame, the new mode applies from there
+        let frame = self.frame_of(self.position(now).floor() as u64);
+        self.mode = mode;
+        self.anchor_position = self.unfold(frame, false);
+        if self.is_playing() {
+            self.anchor_time = Some(now);
+        }
+        self.last_position = None;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.anchor_time.is_some()
+    }
+
+    /// Frames dropped since playback was created.
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn play(&mut self, now: Duration) {
+        if self.is_playing() {
+            return;
+        }
+        // A single-shot cine that has ended starts over
+        if self.mode == PlaybackMode::Once && self.anchor_position as usize >= self.frame_count - 1
+        {
+            self.anchor_position = 0.0;
+        }
+        self.anchor_time = Some(now);
+        self.last_position = None;
+    }
+
+    pub fn pause(&mut self, now: Duration) {
+        self.anchor_position = self.position(now).floor();
+        self.anchor_time = None;
+    }
+
+    /// Pauses and moves `delta` frames forward (or backward if negative). Loop mode wraps
+    /// around, the other modes stop at the first and last frame.
+    pub fn step(&mut self, now: Duration, delta: i64) {
+        let descending = self.is_descending(now);
+        self.pause(now);
+        let frame = self.frame_of(self.anchor_position as u64) as i64;
+        let count = self.frame_count as i64;
+        let frame = match self.mode {
+            PlaybackMode::Loop => (frame + delta).rem_euclid(count),
+            PlaybackMode::Bounce | PlaybackMode::Once => (frame + delta).clamp(0, count - 1),
+        };
+        self.anchor_position = self.unfold(frame as usize, descending);
+    }
+
+    /// Jumps to a frame, playback continues from there if playing.
+    pub fn seek(&mut self, now: Duration, frame: usize) {
+        let frame = frame.min(self.frame_count - 1);
+        self.anchor_position = self.unfold(frame, false);
+        if self.is_playing() {
+            self.anchor_time = Some(now);
+        }
+        self.last_position = None;
+    }
+
+    pub fn set_frames_per_second(&mut self, now: Duration, frames_per_second: f64) -> Result<()> {
+        Self::check_rate(frames_per_second)?;
+        // Continue from the current position at the new rate
+        if self.is_playing() {
+            self.anchor_position = self.position(now);
+            self.anchor_time = Some(now);
+        }
+        self.frames_per_second = frames_per_second;
+        Ok(())
+    }
+
+    /// The frame to show at `now`. Single-shot playback pauses on the last frame.
+    pub fn tick(&mut self, now: Duration) -> CineTick {
+        let mut position = self.position(now).floor() as u64;
+        if self.mode == PlaybackMode::Once && position as usize >= self.frame_count - 1 {
+            position = (self.frame_count - 1) as u64;
+            self.anchor_position = position as f64;
+            self.anchor_time = None;
+        }
+        let dropped = match self.last_position {
+            Some(last) if position > last + 1 => position - last - 1,
+            _ => 0,
+        };
+        self.dropped += dropped;
+        self.last_position = Some(position);
+        CineTick {
+            frame: self.frame_of(position),
+            dropped,
+        }
+    }
+
+    /// Sets `ViewState::frame` to the frame to show at `now`.
+    pub fn update(&mut self, now: Duration, state: &mut ViewState) -> CineTick {
+        let tick = self.tick(now);
+        state.frame = Some(tick.frame as u32);
+        tick
+    }
+
+    fn position(&self, now: Duration) -> f64 {
+        match self.anchor_time {
+            Some(start) => {
+                let elapsed = now.checked_sub(start).unwrap_or_default();
+                // In nanoseconds, so frame boundaries at whole milliseconds are exact
+                let frames = elapsed.as_nanos() as f64 * self.frames_per_second / 1e9;
+                self.anchor_position + frames
+            }
+            None => self.anchor_position,
+        }
+    }
+
+    // Length of one bounce, forwards and back, in frames.
+    fn bounce_period(&self) -> u64 {
+        (2 * (self.frame_count - 1)).max(1) as u64
+    }
+
+    // Folds a position of the unfolded timeline into a frame index.
+    fn frame_of(&self, position: u64) -> usize {
+        let count = self.frame_count as u64;
+        let frame = match self.mode {
+            PlaybackMode::Loop => position % count,
+            PlaybackMode::Bounce => {
+                let period = self.bounce_period();
+                let phase = position % period;
+                if phase < count {
+                    phase
+                } else {
+                    period - phase
+                }
+            }
+            PlaybackMode::Once => position.min(count - 1),
+        };
+        frame as usize
+    }
+
+    // A position of the unfolded timeline showing `frame`, on the way back when bouncing.
+    fn unfold(&self, frame: usize, descending: bool) -> f64 {
+        if self.mode == PlaybackMode::Bounce && descending && frame > 0 {
+            (self.bounce_period() - frame as u64) as f64
+        } else {
+            frame as f64
+        }
+    }
+
+    fn is_descending(&self, now: Duration) -> bool {
+        self.mode == PlaybackMode::Bounce
+            && self.position(now) as u64 % self.bounce_period() >= self.frame_count as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn frames(cine: &mut CineController, times: &[u64]) -> Vec<usize> {
+        times.iter().map(|&t| cine.tick(ms(t)).frame).collect()
+    }
+
+    #[test]
+    fn loops_at_the_frame_rate() {
+        let mut cine = CineController::new(3, 10.0).unwrap();
+        cine.play(ms(1000));
+        assert_eq!(
+            frames(&mut cine, &[1000, 1100, 1200, 1300, 1450]),
+            vec![0, 1, 2, 0, 1]
+        );
+    }
+
+    #[test]
+    fn bounces_and_stops() {
+        let mut cine = CineController::new(3, 10.0).unwrap();
+        cine.set_mode(ms(0), PlaybackMode::Bounce);
+        cine.play(ms(0));
+        let times: Vec<u64> = (0..6).map(|i| i * 100).collect();
+        assert_eq!(frames(&mut cine, &times), vec![0, 1, 2, 1, 0, 1]);
+
+        let mut cine = CineController::new(3, 10.0).unwrap();
+        cine.set_mode(ms(0), PlaybackMode::Once);
+        cine.play(ms(0));
+        assert_eq!(frames(&mut cine, &[0, 100, 200, 900]), vec![0, 1, 2, 2]);
+        assert!(!cine.is_playing());
+    }
+
+    #[test]
+    fn counts_dropped_frames() {
+        let mut cine = CineController::new(10, 10.0).unwrap();
+        cine.play(ms(0));
+        cine.tick(ms(0));
+        let tick = cine.tick(ms(350));
+        assert_eq!(
+            tick,
+            CineTick {
+                frame: 3,
+                dropped: 2
+            }
+        );
+        assert_eq!(cine.dropped_frames(), 2);
+    }
+
+    #[test]
+    fn pause_and_step() {
+        let mut cine = CineController::new(4, 10.0).unwrap();
+        cine.play(ms(0));
+        cine.pause(ms(250));
+        assert_eq!(frames(&mut cine, &[250, 5000]), vec![2, 2]);
+        cine.step(ms(5000), 1);
+        assert_eq!(cine.tick(ms(5000)).frame, 3);
+        cine.step(ms(5000), 1);
+        assert_eq!(cine.tick(ms(5000)).frame, 0);
+        cine.step(ms(5000), -1);
+        assert_eq!(cine.tick(ms(5000)).frame, 3);
+        // Playback resumes from the stepped frame
+        cine.play(ms(6000));
+        assert_eq!(frames(&mut cine, &[6000, 6100]), vec![3, 0]);
+    }
+
+    #[test]
+    fn rejects_empty_cines_and_bad_rates() {
+        assert!(CineController::new(0, 10.0).is_err());
+        assert!(CineController::new(3, 0.0).is_err());
+        assert!(CineController::new(3, f64::NAN).is_err());
+        let mut cine = CineController::new(3, 10.0).unwrap();
+        assert!(cine.set_frames_per_second(ms(0), -1.0).is_err());
+        assert!(cine.set_frames_per_second(ms(0), 20.0).is_ok());
+    }
+
+    #[test]
+    fn step_keeps_bounce_direction() {
+        let mut cine = CineController::new(4, 10.0).unwrap();
+        cine.set_mode(ms(0), PlaybackMode::Bounce);
+        cine.play(ms(0));
+        // 0 1 2 3 2, on the way back
+        cine.step(ms(450), -1);
+        assert_eq!(cine.tick(ms(450)).frame, 1);
+        cine.play(ms(1000));
+        assert_eq!(frames(&mut cine, &[1000, 1100, 1200]), vec![1, 0, 1]);
+    }
+}
@@ -0,0 +1,68 @@
+use crate::{
+    pixel_format::{PixelFormat, SampleType},
+    rendergl::shader::ShaderError,
+};
+use derive_more::{Display, Error};
+
+/// Errors reported by the uploader and the renderer.
+#[derive(Debug, Display, Error)]
+pub enum Error {
+    /// The uploader thread has been shut down.
+    #[display(fmt = "The uploader thread is not running")]
+    UploaderGone,
+    /// The uploader thread died, carries the panic message.
+    #[display(fmt = "The uploader thread panicked: {}", _0)]
+    UploaderPanicked(#[error(not(source))] String),
+    /// Something failed on the uploader thread, reported back over `UploaderMessage::Fail`.
+    #[display(fmt = "Uploader failed: {}", _0)]
+    Uploader(#[error(not(source))] String),
+    #[display(fmt = "Unexpected reply from the uploader: {}", _0)]
+    UnexpectedReply(#[error(not(source))] String),
+    /// The backend selected in the environment is not known, carries the variable and
+    /// the accepted names.
+    #[display(fmt = "Invalid headless backend: {}", _0)]
+    UnknownBackend(#[error(not(source))] String),
+    /// The uploader configuration was rejected, no thread was started.
+    #[display(fmt = "Invalid uploader configuration: {}", _0)]
+    InvalidConfig(#[error(not(source))] String),
+    /// The headless GL context could not be created, wrapped or made current.
+    #[display(fmt = "GL context error: {}", _0)]
+    Context(#[error(not(source))] String),
+    /// A GL call on the uploader thread failed, e.g. out of memory.
+    #[display(fmt = "GL error: {}", _0)]
+    Gl(#[error(not(source))] String),
+    /// Bits stored outside of 1 to 16.
+    #[display(fmt = "Bits stored has to be 1 to 16, got {}", _0)]
+    InvalidBitDepth(#[error(not(source))] u8),
+    /// A LUT stage or palette without entries, or LUT entries of more than 16 bits.
+    #[display(fmt = "Invalid LUT: {}", _0)]
+    InvalidLut(#[error(not(source))] String),
+    /// The cine playback was set up with no frames or a frame rate that is not positive.
+    #[display(fmt = "Invalid cine playback: {}", _0)]
+    InvalidCine(#[error(not(source))] String),
+    /// The data or size passed to a load does not match the texture.
+    #[display(fmt = "Invalid upload: {}", _0)]
+    InvalidUpload(#[error(not(source))] String),
+    #[display(fmt = "{:?} samples requested from a {:?} texture", _0, _1)]
+    SampleTypeMismatch(SampleType, PixelFormat),
+    /// The draw settings of the program can not be applied.
+    #[display(fmt = "Invalid draw settings: {}", _0)]
+    InvalidSettings(#[error(not(source))] String),
+    /// The program can not draw textures of the format, e.g. a colour image as mono.
+    #[display(fmt = "The {} program can not draw a {:?} texture", _0, _1)]
+    ProgramMismatch(&'static str, PixelFormat),
+    /// The program reads a LUT, but the draw has none.
+    #[display(fmt = "The program reads a LUT, none was given")]
+    MissingLut,
+    /// The texture has been released, its GL name may belong to another texture.
+    #[display(fmt = "Texture {} has been released", _0)]
+    StaleTexture(#[error(not(source))] u32),
+    /// A shader failed to compile or link, carries the driver's info log.
+    #[display(fmt = "{}", _0)]
+    Shader(#[error(not(source))] ShaderError),
+    /// The other end of the render channel has been dropped.
+    #[display(fmt = "The render channel is closed")]
+    RenderChannelClosed,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
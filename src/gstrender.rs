@@ -1,15 +1,41 @@
-use crate::{bidir::BidirChannel, rendergl, texture::{TextureDescription, TextureHandle}};
+use crate::{
+    bidir::BidirChannel,
+    error::{Error, Result},
+    region::Region,
+    rendergl::{
+        self,
+        glrenderer::{GlRenderer, ImageProgram},
+        vertex,
+        view_state::ViewState,
+    },
+    texture::TextureDescription,
+    upload_token::UploadToken,
+};
 use gst_gl::GLContextExtManual;
 use gstreamer_gl as gst_gl;
 use std::sync::mpsc::Receiver;
 
 #[derive(Debug)]
-pub struct GstRenderMessage {
-    pub image_texture: TextureDescription,
-    pub lut_texture: TextureDescription,
+pub struct ImageQuad {
+    pub texture: TextureDescription,
     pub vertex_data: Vec<rendergl::vertex::Vertex>,
 }
 
+#[derive(Debug)]
+pub struct GstRenderMessage {
+    // One quad per texture, images larger than the texture limit are drawn as several tiles.
+    pub image_quads: Vec<ImageQuad>,
+    // Only read by the mono program without a uniform window, may be left out otherwise.
+    pub lut_texture: Option<TextureDescription>,
+    // Mono images are drawn through the LUT or a uniform window, colour images with the
+    // ARGB program.
+    pub program: ImageProgram,
+    // Uploads of the textures above that have to complete before they are sampled.
+    pub uploads: Vec<UploadToken>,
+    // Selects the frame of multi-frame images and the filtering.
+    pub view_state: ViewState,
+}
+
 pub struct GstRenderStruct {
     renderer: rendergl::glrenderer::GlRenderer,
     channel: BidirChannel<GstRenderMessage>,
@@ -17,32 +43,79 @@ pub struct GstRenderStruct {
 }
 
 impl GstRenderStruct {
-    pub fn new(context: gst_gl::GLContext, channel: BidirChannel<GstRenderMessage>) -> Self {
+    pub fn new(
+        context: gst_gl::GLContext,
+        channel: BidirChannel<GstRenderMessage>,
+    ) -> Result<Self> {
         let renderer = rendergl::glrenderer::GlRenderer::new(|name| {
             context.get_proc_address(name) as *const _
-        });
-        Self {
+        })?;
+        Ok(Self {
             renderer,
             channel,
             _ctx: context,
-        }
+        })
     }
 
-    pub unsafe fn draw(&self) {
+    pub unsafe fn draw(&self) -> Result<()> {
         let message = self
             .channel
             .recv()
-            .expect("Failed to receive RenderMessage");
+            .map_err(|_| Error::RenderChannelClosed)?;
         println!("Received message {:?}", message);
 
+        // Have the GStreamer context wait for the uploader context to finish writing.
+        for upload in &message.uploads {
+            upload.with_pending_fence(|fence| self.renderer.wait_sync(fence as _));
+        }
+
+        // Only the loaded parts of a texture are drawn, the rest shows a placeholder.
+        let mut clipped = Vec::new();
+        let mut placeholders = Vec::new();
+        for quad in &message.image_quads {
+            let texture = &quad.texture;
+            let layer = GlRenderer::layer(texture.handle.texture(), &message.view_state);
+            // The uploads waited for above are visible to this context already, the layer
+            // is clamped to the frames of the texture so there always is a region.
+            let valid = texture
+                .synced_region(layer, &message.uploads)
+                .unwrap_or_default();
+            if valid.covers(&Region::new((0, 0), texture.size)) {
+                clipped.push((quad.vertex_data.clone(), texture.handle.texture()));
+                continue;
+            }
+            placeholders.push(quad.vertex_data.as_slice());
+            let texture_size = (texture.handle.width as f32, texture.handle.height as f32);
+            for rect in valid.rects() {
+                let rect = (
+                    (rect.offset.0 as f32, rect.offset.1 as f32),
+                    (rect.size.0 as f32, rect.size.1 as f32),
+                );
+                if let Some(vertices) =
+                    vertex::clip_to_texels(&quad.vertex_data, texture_size, rect)
+                {
+                    clipped.push((vertices, texture.handle.texture()));
+                }
+            }
+        }
+        let quads: Vec<_> = clipped
+            .iter()
+            .map(|(vertices, texture)| (vertices.as_slice(), *texture))
+            .collect();
         self.renderer.draw(
-            &message.vertex_data,
-            message.image_texture.handle.id,
-            message.lut_texture.handle.id,
-        );
+            &quads,
+            &placeholders,
+            message
+                .lut_texture
+                .as_ref()
+                .map(|lut_texture| lut_texture.handle.texture()),
+            message.program,
+            &message.view_state,
+        )?;
         // Send the message back signalling that we are done
         // self.channel
         //     .send(message)
         //     .expect("Failed to send RenderMessage back to main");
+        Ok(())
     }
 }
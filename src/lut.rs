@@ -0,0 +1,849 @@
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// Number of entries of a LUT, one per 16 bit stored value. The uploader stores them in a
+/// 256x256 texture, indexed by the stored value in `glfrag.glsl`.
+pub const LUT_ENTRIES: usize = 1 << 16;
+
+/// How the stored values of the image are to be read, the image is uploaded as R16.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BitDepth {
+    /// Number of significant bits, DICOM Bits Stored.
+    pub bits_stored: u8,
+    /// Two's complement values, DICOM Pixel Representation 1.
+    pub signed: bool,
+}
+
+impl BitDepth {
+    pub fn unsigned(bits_stored: u8) -> Result<Self> {
+        Self::new(bits_stored, false)
+    }
+
+    pub fn signed(bits_stored: u8) -> Result<Self> {
+        Self::new(bits_stored, true)
+    }
+
+    fn new(bits_stored: u8, signed: bool) -> Result<Self> {
+        if !(1..=16).contains(&bits_stored) {
+            return Err(Error::InvalidBitDepth(bits_stored));
+        }
+        Ok(Self {
+            bits_stored,
+            signed,
+        })
+    }
+
+    /// The smallest and largest value the bits can hold.
+    pub fn range(&self) -> (f64, f64) {
+        let bits = self.bits_stored as i32;
+        if self.signed {
+            (-(2_f64.powi(bits - 1)), 2_f64.powi(bits - 1) - 1.0)
+        } else {
+            (0.0, 2_f64.powi(bits) - 1.0)
+        }
+    }
+
+    /// The value of a 16 bit stored value. Signed values are read as sign extended to 16
+    /// bits, values outside of the bit depth are clamped to its range.
+    pub fn value(&self, stored: u16) -> f64 {
+        let value = if self.signed {
+            stored as i16 as f64
+        } else {
+            stored as f64
+        };
+        let (min, max) = self.range();
+        value.clamp(min, max)
+    }
+}
+
+impl Default for BitDepth {
+    fn default() -> Self {
+        Self {
+            bits_stored: 16,
+            signed: false,
+        }
+    }
+}
+
+/// The VOI LUT functions of DICOM PS3.3 C.11.2.1.2.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VoiFunction {
+    Linear,
+    #[serde(rename = "linear_exact")]
+    LinearExact,
+    Sigmoid,
+}
+
+impl Default for VoiFunction {
+    fn default() -> Self {
+        VoiFunction::Linear
+    }
+}
+
+/// Window centre and width, in the units of the values the window is applied to.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Window {
+    pub center: f64,
+    pub width: f64,
+}
+
+impl Window {
+    pub fn new(center: f64, width: f64) -> Self {
+        Self { center, width }
+    }
+
+    /// The window covering every value of the bit depth.
+    pub fn full_range(bit_depth: BitDepth) -> Self {
+        let (min, max) = bit_depth.range();
+        Self::new((min + max + 1.0) / 2.0, max - min + 1.0)
+    }
+
+    /// Output in [0, 1] for the input value `x`.
+    pub fn apply(&self, function: VoiFunction, x: f64) -> f64 {
+        let (c, w) = (self.center, self.width);
+        match function {
+            VoiFunction::Linear => {
+                // The width is at least 1 for the linear function
+                let w = w.max(1.0);
+                if x <= c - 0.5 - (w - 1.0) / 2.0 {
+                    0.0
+                } else if x > c - 0.5 + (w - 1.0) / 2.0 {
+                    1.0
+                } else {
+                    (x - (c - 0.5)) / (w - 1.0) + 0.5
+                }
+            }
+            VoiFunction::LinearExact => {
+                let w = w.max(f64::MIN_POSITIVE);
+                if x <= c - w / 2.0 {
+                    0.0
+                } else if x > c + w / 2.0 {
+                    1.0
+                } else {
+                    (x - c) / w + 0.5
+                }
+            }
+            VoiFunction::Sigmoid => {
+                let w = w.max(f64::MIN_POSITIVE);
+                1.0 / (1.0 + (-4.0 * (x - c) / w).exp())
+            }
+        }
+    }
+}
+
+/// Builds the LUT applying a window to the stored values, regenerate and upload it with
+/// `ThreadUploader::load_lut` to change the window interactively. The same table as a
+/// `LutPipeline` with just a VOI window, use the pipeline for modality or presentation
+/// stages.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct VoiLut {
+    pub window: Window,
+    pub function: VoiFunction,
+    /// Show high values dark, as for MONOCHROME1 images.
+    pub invert: bool,
+    pub bit_depth: BitDepth,
+}
+
+impl VoiLut {
+    pub fn new(window: Window) -> Self {
+        Self {
+            window,
+            function: VoiFunction::default(),
+            invert: false,
+            bit_depth: BitDepth::default(),
+        }
+    }
+
+    /// Output in [0, 1] for a 16 bit stored value.
+    pub fn apply(&self, stored: u16) -> f64 {
+        let y = self
+            .window
+            .apply(self.function, self.bit_depth.value(stored));
+        if self.invert {
+            1.0 - y
+        } else {
+            y
+        }
+    }
+
+    /// The table of `LUT_ENTRIES` entries, indexed by the stored value.
+    pub fn generate(&self) -> Vec<u16> {
+        (0..LUT_ENTRIES)
+            .map(|stored| to_entry(self.apply(stored as u16)))
+            .collect()
+    }
+}
+
+/// A linear window applied by the shader instead of through a LUT texture, so changing
+/// it needs no upload. Gives the same output as a `LutPipeline` with the same rescale and
+/// bit depth, `VoiFunction::Linear` and an identity or inverse presentation.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct UniformWindow {
+    pub window: Window,
+    pub slope: f64,
+    pub intercept: f64,
+    pub invert: bool,
+    pub bit_depth: BitDepth,
+}
+
+impl UniformWindow {
+    pub fn new(window: Window) -> Self {
+        Self {
+            window,
+            slope: 1.0,
+            intercept: 0.0,
+            invert: false,
+            bit_depth: BitDepth::default(),
+        }
+    }
+
+    /// Output in [0, 1] for a stored value, computed in f32 as the shader does. Signed
+    /// values may be passed as their 16 bit two's complement, as loaded into R16.
+    pub fn apply(&self, stored: f32) -> f32 {
+        let stored = if self.bit_depth.signed && stored > i16::MAX as f32 {
+            stored - (1 << 16) as f32
+        } else {
+            stored
+        };
+        let (min, max) = self.bit_depth.range();
+        self.apply_float(stored.clamp(min as f32, max as f32))
+    }
+
+    /// Output in [0, 1] for a float sample, which is neither sign extended nor clamped to
+    /// the bit depth.
+    pub fn apply_float(&self, value: f32) -> f32 {
+        let x = value * self.slope as f32 + self.intercept as f32;
+        let (c, w) = (
+            self.window.center as f32,
+            (self.window.width as f32).max(1.0),
+        );
+        let y = if x <= c - 0.5 - (w - 1.0) / 2.0 {
+            0.0
+        } else if x > c - 0.5 + (w - 1.0) / 2.0 {
+            1.0
+        } else {
+            (x - (c - 0.5)) / (w - 1.0) + 0.5
+        };
+        if self.invert {
+            1.0 - y
+        } else {
+            y
+        }
+    }
+}
+
+/// A LUT entry for an output in [0, 1].
+pub fn to_entry(y: f64) -> u16 {
+    (y.clamp(0.0, 1.0) * u16::MAX as f64).round() as u16
+}
+
+/// A table as stored in DICOM, entry `i` is the output for the input `first_mapped + i`.
+/// Inputs outside of the table get the first or last entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MappedLut {
+    pub first_mapped: i32,
+    pub entries: Vec<u16>,
+}
+
+impl MappedLut {
+    /// Lookups need at least one entry, `LutPipeline` checks its stages with this.
+    pub fn validate(&self) -> Result<()> {
+        check_entries(&self.entries)
+    }
+
+    /// `None` if the LUT has no entries.
+    pub fn lookup(&self, x: f64) -> Option<u16> {
+        let last = self.entries.len() as i64 - 1;
+        if last < 0 {
+            return None;
+        }
+        let index = (x.round() as i64 - self.first_mapped as i64).clamp(0, last);
+        Some(self.entries[index as usize])
+    }
+}
+
+/// Converts stored values to modality values, e.g. Hounsfield units.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Modality {
+    /// Rescale Slope and Rescale Intercept, `slope * stored + intercept`.
+    Rescale {
+        slope: f64,
+        intercept: f64,
+    },
+    Lut(MappedLut),
+}
+
+impl Modality {
+    /// `None` for a LUT without entries.
+    pub fn apply(&self, x: f64) -> Option<f64> {
+        match self {
+            Modality::Rescale { slope, intercept } => Some(slope * x + intercept),
+            Modality::Lut(lut) => lut.lookup(x).map(f64::from),
+        }
+    }
+}
+
+impl Default for Modality {
+    fn default() -> Self {
+        Modality::Rescale {
+            slope: 1.0,
+            intercept: 0.0,
+        }
+    }
+}
+
+/// Selects the range of modality values to show, output in [0, 1].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Voi {
+    /// The whole range of the modality values, linearly.
+    None,
+    Window {
+        window: Window,
+        function: VoiFunction,
+    },
+    /// A VOI LUT whose entries use `bits` bits.
+    Lut { lut: MappedLut, bits: u8 },
+}
+
+impl Default for Voi {
+    fn default() -> Self {
+        Voi::None
+    }
+}
+
+/// Maps the VOI output to display values, input and output in [0, 1].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Presentation {
+    Identity,
+    /// Low values bright, e.g. for MONOCHROME1.
+    Inverse,
+    /// Entries spread evenly over the input range, using `bits` bits.
+    Lut {
+        entries: Vec<u16>,
+        bits: u8,
+    },
+}
+
+impl Default for Presentation {
+    fn default() -> Self {
+        Presentation::Identity
+    }
+}
+
+impl Presentation {
+    pub fn apply(&self, y: f64) -> f64 {
+        match self {
+            Presentation::Identity => y,
+            Presentation::Inverse => 1.0 - y,
+            Presentation::Lut { entries, bits } => {
+                let index = (y * (entries.len() - 1) as f64).round() as usize;
+                entries[index.min(entries.len() - 1)] as f64 / max_value(*bits)
+            }
+        }
+    }
+}
+
+fn max_value(bits: u8) -> f64 {
+    2_f64.powi(bits as i32) - 1.0
+}
+
+fn check_entries(entries: &[u16]) -> Result<()> {
+    match entries.is_empty() {
+        true => Err(Error::InvalidLut("A LUT needs at least one entry".into())),
+        false => Ok(()),
+    }
+}
+
+fn check_bits(bits: u8) -> Result<()> {
+    match bits {
+        1..=16 => Ok(()),
+        _ => Err(Error::InvalidLut(format!(
+            "LUT entries have 1 to 16 bits, got {}",
+            bits
+        ))),
+    }
+}
+
+/// Composes the modality, VOI and presentation stages into the single table the shader
+/// looks up. The output of each stage is cached, changing a stage only recomputes the
+/// stages from it on, e.g. a new window leaves the modality values alone.
+#[derive(Debug, Clone, Default)]
+pub struct LutPipeline {
+    bit_depth: BitDepth,
+    modality: Modality,
+    voi: Voi,
+    presentation: Presentation,
+    // One value per stored value, dropped when a stage before them changes.
+    modality_values: Option<Vec<f64>>,
+    voi_values: Option<Vec<f64>>,
+    table: Option<Vec<u16>>,
+}
+
+impl LutPipeline {
+    pub fn new(bit_depth: BitDepth) -> Self {
+        Self {
+            bit_depth,
+            ..Default::default()
+        }
+    }
+
+    pub fn bit_depth(&self) -> BitDepth {
+        self.bit_depth
+    }
+
+    pub fn modality(&self) -> &Modality {
+        &self.modality
+    }
+
+    pub fn voi(&self) -> &Voi {
+        &self.voi
+    }
+
+    pub fn presentation(&self) -> &Presentation {
+        &self.presentation
+    }
+
+    pub fn set_bit_depth(&mut self, bit_depth: BitDepth) {
+        self.bit_depth = bit_depth;
+        self.modality_values = None;
+        self.voi_values = None;
+        self.table = None;
+    }
+
+    /// Rejects an empty modality LUT, the pipeline is left unchanged.
+    pub fn set_modality(&mut self, modality: Modality) -> Result<()> {
+        if let Modality::Lut(lut) = &modality {
+            lut.validate()?;
+        }
+        self.modality = modality;
+        self.modality_values = None;
+        self.voi_values = None;
+        self.table = None;
+        Ok(())
+    }
+
+    /// Rejects an empty VOI LUT or one with more than 16 bits, the pipeline is left
+    /// unchanged.
+    pub fn set_voi(&mut self, voi: Voi) -> Result<()> {
+        if let Voi::Lut { lut, bits } = &voi {
+            lut.validate()?;
+            check_bits(*bits)?;
+        }
+        self.voi = voi;
+        self.voi_values = None;
+        self.table = None;
+        Ok(())
+    }
+
+    /// Rejects an empty presentation LUT or one with more than 16 bits, the pipeline is
+    /// left unchanged.
+    pub fn set_presentation(&mut self, presentation: Presentation) -> Result<()> {
+        if let Presentation::Lut { entries, bits } = &presentation {
+            check_entries(entries)?;
+            check_bits(*bits)?;
+        }
+        self.presentation = presentation;
+        self.table = None;
+        Ok(())
+    }
+
+    /// The composed table of `LUT_ENTRIES` entries, for `ThreadUploader::load_lut`.
+    pub fn table(&mut self) -> &[u16] {
+        if self.table.is_none() {
+            self.update_voi_values();
+            let voi_values = self.voi_values.as_deref().unwrap();
+            let table = voi_values
+                .iter()
+                .map(|&y| to_entry(self.presentation.apply(y)))
+                .collect();
+            self.table = Some(table);
+        }
+        self.table.as_deref().unwrap()
+    }
+
+    fn update_modality_values(&mut self) {
+        if self.modality_values.is_none() {
+            let values = (0..LUT_ENTRIES)
+                .map(|stored| {
+                    // Empty LUTs are rejected by `set_modality`
+                    let value = self.bit_depth.value(stored as u16);
+                    self.modality.apply(value).unwrap_or_default()
+                })
+                .collect();
+            self.modality_values = Some(values);
+        }
+    }
+
+    fn update_voi_values(&mut self) {
+        if self.voi_values.is_some() {
+            return;
+        }
+        self.update_modality_values();
+        let modality_values = self.modality_values.as_deref().unwrap();
+        let values = match &self.voi {
+            Voi::None => {
+                let min = modality_values
+                    .iter()
+                    .cloned()
+                    .fold(f64::INFINITY, f64::min);
+                let max = modality_values
+                    .iter()
+                    .cloned()
+                    .fold(f64::NEG_INFINITY, f64::max);
+                let window = Window::new((min + max + 1.0) / 2.0, max - min + 1.0);
+                modality_values
+                    .iter()
+                    .map(|&x| window.apply(VoiFunction::Linear, x))
+                    .collect()
+            }
+            Voi::Window { window, function } => modality_values
+                .iter()
+                .map(|&x| window.apply(*function, x))
+                .collect(),
+            // Empty LUTs are rejected by `set_voi`
+            Voi::Lut { lut, bits } => modality_values
+                .iter()
+                .map(|&x| lut.lookup(x).unwrap_or_default() as f64 / max_value(*bits))
+                .collect(),
+        };
+        self.voi_values = Some(values);
+    }
+}
+
+/// Colours for pseudo-colour display, evenly spread over the output of a grey LUT and
+/// interpolated in between. Drawn from a palette LUT texture, see `load_palette_lut`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "PaletteColors")]
+pub struct Palette {
+    colors: Vec<[u8; 4]>,
+}
+
+// A palette as deserialized, before the colours are checked.
+#[derive(Deserialize)]
+struct PaletteColors {
+    colors: Vec<[u8; 4]>,
+}
+
+impl TryFrom<PaletteColors> for Palette {
+    type Error = Error;
+
+    fn try_from(palette: PaletteColors) -> Result<Self> {
+        Self::from_rgba(palette.colors)
+    }
+}
+
+impl Palette {
+    // Entries of the built in palettes
+    const SIZE: usize = 256;
+
+    pub fn from_rgba(colors: Vec<[u8; 4]>) -> Result<Self> {
+        if colors.is_empty() {
+            return Err(Error::InvalidLut(
+                "A palette needs at least one colour".into(),
+            ));
+        }
+        Ok(Self { colors })
+    }
+
+    /// Opaque colours, e.g. from the RGB palette LUTs of a DICOM image.
+    pub fn from_rgb(colors: &[[u8; 3]]) -> Result<Self> {
+        Self::from_rgba(colors.iter().map(|&[r, g, b]| [r, g, b, u8::MAX]).collect())
+    }
+
+    /// Black through red and yellow to white.
+    pub fn hot_iron() -> Self {
+        Self::from_fn(|t| [2.0 * t, 2.0 * t - 1.0, 4.0 * t - 3.0])
+    }
+
+    /// Blue through cyan, yellow and red, as in Matlab.
+    pub fn jet() -> Self {
+        let ramp = |t: f64, peak: f64| 1.5 - (4.0 * t - peak).abs();
+        Self::from_fn(|t| [ramp(t, 3.0), ramp(t, 2.0), ramp(t, 1.0)])
+    }
+
+    /// The hues from blue to red at full saturation.
+    pub fn rainbow() -> Self {
+        Self::from_fn(|t| {
+            let hue = (1.0 - t) * 4.0; // In sixths of the hue circle, 4 is blue
+            let channel = |offset: f64| {
+                let k = (offset + hue) % 6.0;
+                1.0 - k.min(4.0 - k).clamp(0.0, 1.0)
+            };
+            [channel(5.0), channel(3.0), channel(1.0)]
+        })
+    }
+
+    pub fn colors(&self) -> &[[u8; 4]] {
+        &self.colors
+    }
+
+    /// The colour at `t` in [0, 1].
+    pub fn color(&self, t: f64) -> [u8; 4] {
+        let last = self.colors.len() - 1;
+        let x = t.clamp(0.0, 1.0) * last as f64;
+        let index = (x.floor() as usize).min(last);
+        let (a, b) = (self.colors[index], self.colors[(index + 1).min(last)]);
+        let f = x - index as f64;
+        let mut color = [0; 4];
+        for (c, (a, b)) in color.iter_mut().zip(a.iter().zip(b.iter())) {
+            *c = (*a as f64 + (*b as f64 - *a as f64) * f).round() as u8;
+        }
+        color
+    }
+
+    /// Colours the entries of a grey LUT, e.g. `LutPipeline::table`. The result is
+    /// RGBA, four samples per entry.
+    pub fn apply(&self, table: &[u16]) -> Vec<u8> {
+        table
+            .iter()
+            .flat_map(|&entry| self.color(entry as f64 / u16::MAX as f64))
+            .collect()
+    }
+
+    fn from_fn<F: Fn(f64) -> [f64; 3]>(f: F) -> Self {
+        let to_u8 = |c: f64| (c.clamp(0.0, 1.0) * u8::MAX as f64).round() as u8;
+        let colors = (0..Self::SIZE)
+            .map(|i| {
+                let [r, g, b] = f(i as f64 / (Self::SIZE - 1) as f64);
+                [to_u8(r), to_u8(g), to_u8(b), u8::MAX]
+            })
+            .collect();
+        Self { colors }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear_matches_dicom() {
+        // Centre 2048 and width 4096 maps [0, 4095] onto the full output range
+        let window = Window::new(2048.0, 4096.0);
+        let f = VoiFunction::Linear;
+        assert_eq!(window.apply(f, -1.0), 0.0);
+        assert_eq!(window.apply(f, 0.0), 0.0);
+        assert!((window.apply(f, 2047.5) - 0.5).abs() < 1e-9);
+        assert_eq!(window.apply(f, 4095.0), 1.0);
+        // Width 1 is a threshold
+        let window = Window::new(100.0, 1.0);
+        assert_eq!(window.apply(f, 99.0), 0.0);
+        assert_eq!(window.apply(f, 100.0), 1.0);
+    }
+
+    #[test]
+    fn linear_exact_and_sigmoid() {
+        let window = Window::new(0.0, 100.0);
+        assert_eq!(window.apply(VoiFunction::LinearExact, -50.0), 0.0);
+        assert_eq!(window.apply(VoiFunction::LinearExact, 25.0), 0.75);
+        assert_eq!(window.apply(VoiFunction::LinearExact, 51.0), 1.0);
+        assert_eq!(window.apply(VoiFunction::Sigmoid, 0.0), 0.5);
+        assert!(window.apply(VoiFunction::Sigmoid, 100.0) > 0.98);
+    }
+
+    #[test]
+    fn clamps_to_bit_depth() {
+        let depth = BitDepth::unsigned(12).unwrap();
+        assert_eq!(depth.value(4095), 4095.0);
+        assert_eq!(depth.value(60000), 4095.0);
+        let depth = BitDepth::signed(12).unwrap();
+        assert_eq!(depth.value((-5_i16) as u16), -5.0);
+        assert_eq!(depth.value((-3000_i16) as u16), -2048.0);
+        assert_eq!(depth.value(3000), 2047.0);
+        assert!(BitDepth::unsigned(0).is_err());
+        assert!(BitDepth::signed(17).is_err());
+    }
+
+    #[test]
+    fn generates_inverted_table() {
+        let bit_depth = BitDepth::unsigned(12).unwrap();
+        let mut pipeline = LutPipeline::new(bit_depth);
+        pipeline
+            .set_voi(Voi::Window {
+                window: Window::full_range(bit_depth),
+                function: VoiFunction::Linear,
+            })
+            .unwrap();
+        let table = pipeline.table();
+        assert_eq!(table.len(), LUT_ENTRIES);
+        assert_eq!(table[0], 0);
+        assert_eq!(table[4095], u16::MAX);
+        assert_eq!(table[u16::MAX as usize], u16::MAX);
+        pipeline.set_presentation(Presentation::Inverse).unwrap();
+        assert_eq!(pipeline.table()[0], u16::MAX);
+    }
+
+    #[test]
+    fn voi_lut_matches_the_pipeline() {
+        let bit_depth = BitDepth::signed(12).unwrap();
+        let voi_lut = VoiLut {
+            window: Window::new(100.0, 800.0),
+            function: VoiFunction::Sigmoid,
+            invert: true,
+            bit_depth,
+        };
+        let mut pipeline = LutPipeline::new(bit_depth);
+        pipeline
+            .set_voi(Voi::Window {
+                window: voi_lut.window,
+                function: voi_lut.function,
+            })
+            .unwrap();
+        pipeline.set_presentation(Presentation::Inverse).unwrap();
+        assert_eq!(voi_lut.generate(), pipeline.table());
+    }
+
+    #[test]
+    fn rejects_empty_luts() {
+        let mut pipeline = LutPipeline::new(BitDepth::unsigned(8).unwrap());
+        let empty = MappedLut {
+            first_mapped: 0,
+            entries: vec![],
+        };
+        assert_eq!(empty.lookup(0.0), None);
+        assert!(pipeline.set_modality(Modality::Lut(empty.clone())).is_err());
+        assert!(pipeline
+            .set_voi(Voi::Lut {
+                lut: empty,
+                bits: 8
+            })
+            .is_err());
+        let presentation = Presentation::Lut {
+            entries: vec![],
+            bits: 8,
+        };
+        assert!(pipeline.set_presentation(presentation).is_err());
+        let presentation = Presentation::Lut {
+            entries: vec![0, 255],
+            bits: 0,
+        };
+        assert!(pipeline.set_presentation(presentation).is_err());
+        // Nothing was replaced
+        assert_eq!(pipeline.presentation(), &Presentation::Identity);
+        assert_eq!(pipeline.table()[255], u16::MAX);
+    }
+
+    #[test]
+    fn pipeline_windows_modality_values() {
+        // CT, stored 0..4095 with intercept -1024, a window over [-1000, 1000] HU
+        let mut pipeline = LutPipeline::new(BitDepth::unsigned(12).unwrap());
+        pipeline
+            .set_modality(Modality::Rescale {
+                slope: 1.0,
+                intercept: -1024.0,
+            })
+            .unwrap();
+        pipeline
+            .set_voi(Voi::Window {
+                window: Window::new(0.0, 2000.0),
+                function: VoiFunction::LinearExact,
+            })
+            .unwrap();
+        let table = pipeline.table().to_vec();
+        assert_eq!(table.len(), LUT_ENTRIES);
+        assert_eq!(table[24], 0); // -1000 HU
+        assert_eq!(table[1024], to_entry(0.5)); // 0 HU
+        assert_eq!(table[2024], u16::MAX); // 1000 HU
+
+        // Values outside of the 12 bits are clamped before the rescale
+        assert_eq!(table[60000], u16::MAX);
+
+        pipeline.set_presentation(Presentation::Inverse).unwrap();
+        assert!(pipeline.voi_values.is_some());
+        assert_eq!(pipeline.table()[24], u16::MAX);
+    }
+
+    #[test]
+    fn pipeline_lut_stages() {
+        let mut pipeline = LutPipeline::new(BitDepth::unsigned(8).unwrap());
+        pipeline
+            .set_modality(Modality::Lut(MappedLut {
+                first_mapped: 10,
+                entries: vec![0, 100, 200],
+            }))
+            .unwrap();
+        pipeline
+            .set_voi(Voi::Lut {
+                lut: MappedLut {
+                    first_mapped: 0,
+                    entries: (0..=200).map(|x| x * 255 / 200).collect(),
+                },
+                bits: 8,
+            })
+            .unwrap();
+        let table = pipeline.table();
+        // Below the modality LUT, inside and above
+        assert_eq!(table[0], 0);
+        assert_eq!(table[11], to_entry(127.0 / 255.0));
+        assert_eq!(table[200], u16::MAX);
+
+        // Without a VOI the modality range is shown linearly
+        pipeline.set_voi(Voi::None).unwrap();
+        assert_eq!(pipeline.table()[0], 0);
+        assert_eq!(pipeline.table()[12], u16::MAX);
+    }
+
+    #[test]
+    fn palettes() {
+        let hot_iron = Palette::hot_iron();
+        assert_eq!(hot_iron.color(0.0), [0, 0, 0, 255]);
+        assert_eq!(hot_iron.color(0.5), [255, 1, 0, 255]);
+        assert_eq!(hot_iron.color(1.0), [255, 255, 255, 255]);
+        let jet = Palette::jet();
+        assert_eq!(jet.color(0.0), [0, 0, 128, 255]);
+        assert_eq!(jet.color(1.0), [128, 0, 0, 255]);
+        let rainbow = Palette::rainbow();
+        assert_eq!(rainbow.color(0.0), [0, 0, 255, 255]);
+        assert_eq!(rainbow.color(1.0), [255, 0, 0, 255]);
+
+        // Custom colours are interpolated between the stops
+        let palette = Palette::from_rgb(&[[0, 0, 0], [200, 100, 0]]).unwrap();
+        let rgba = palette.apply(&[0, u16::MAX / 2, u16::MAX]);
+        assert_eq!(rgba, vec![0, 0, 0, 255, 100, 50, 0, 255, 200, 100, 0, 255]);
+        assert!(Palette::from_rgba(vec![]).is_err());
+    }
+
+    #[test]
+    fn uniform_window_matches_the_pipeline() {
+        let bit_depth = BitDepth::signed(12).unwrap();
+        let window = Window::new(40.0, 400.0);
+        let mut pipeline = LutPipeline::new(bit_depth);
+        pipeline
+            .set_modality(Modality::Rescale {
+                slope: 0.5,
+                intercept: -20.0,
+            })
+            .unwrap();
+        pipeline
+            .set_voi(Voi::Window {
+                window,
+                function: VoiFunction::Linear,
+            })
+            .unwrap();
+        pipeline.set_presentation(Presentation::Inverse).unwrap();
+        let uniform = UniformWindow {
+            slope: 0.5,
+            intercept: -20.0,
+            invert: true,
+            bit_depth,
+            ..UniformWindow::new(window)
+        };
+        let table = pipeline.table();
+        for stored in i16::MIN..=i16::MAX {
+            let lut_grey = table[stored as u16 as usize] as f64 / u16::MAX as f64 * 255.0;
+            let uniform_grey = uniform.apply(stored as f32) as f64 * 255.0;
+            assert!(
+                (lut_grey.round() - uniform_grey.round()).abs() <= 1.0,
+                "stored {}: {} vs {}",
+                stored,
+                lut_grey,
+                uniform_grey
+            );
+        }
+    }
+}
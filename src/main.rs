@@ -1,16 +1,28 @@
 mod bidir;
+mod cine;
+mod error;
 mod gstrender;
+mod lut;
+mod pixel_format;
+mod region;
 mod rendergl;
+#[cfg(target_os = "linux")]
+mod surfaceless;
 mod texture;
+mod texture_handle;
+mod texture_pool;
+mod tiles;
+mod upload_token;
 
 use bidir::BidirChannel;
+use cine::{CineController, PlaybackMode};
 use core::time;
 use glib::Value;
 use glutin::{
     dpi::PhysicalSize,
     event::Event,
     event_loop::{ControlFlow, EventLoop},
-    platform::{run_return::EventLoopExtRunReturn, windows::RawHandle, ContextTraitExt},
+    platform::{run_return::EventLoopExtRunReturn, ContextTraitExt},
     PossiblyCurrent,
 };
 use gst::prelude::*;
@@ -19,8 +31,9 @@ use gstreamer as gst;
 use gstreamer_app as gst_app;
 use gstreamer_gl as gst_gl;
 use gstreamer_video as gst_video;
-use gstrender::{GstRenderStruct, GstRenderMessage};
-use rendergl::{vertex::Quad, view_state::ViewState};
+use gstrender::{GstRenderMessage, GstRenderStruct, ImageQuad};
+use pixel_format::{PixelFormat, Sample};
+use rendergl::{glrenderer::ImageProgram, vertex::Quad, view_state::ViewState};
 use std::{
     sync::{
         mpsc::{self, Receiver},
@@ -28,7 +41,7 @@ use std::{
     },
     time::Duration,
 };
-use texture::ThreadUploader;
+use texture::{HeadlessBackend, TextureDescription, ThreadUploader, UploaderConfig};
 
 const IMAGE_WIDTH: usize = 256;
 const IMAGE_HEIGHT: usize = 256;
@@ -45,18 +58,27 @@ pub fn generate_texture_data(f: f32) -> Vec<u16> {
 }
 
 pub fn generate_lut_data() -> Vec<u16> {
-    let data_size = 256 * 256;
-    let mut data = vec![0_u16; data_size];
-    for (i, entry) in data.iter_mut().enumerate() {
-        let sv = (i) % u16::MAX as usize;
-        *entry = sv as u16;
+    // No rescale and no window, the identity ramp over all 16 bits
+    let mut pipeline = lut::LutPipeline::new(lut::BitDepth::default());
+    pipeline.table().to_vec()
+}
+
+// Every displayed value goes through the LUT, make sure it arrived intact
+fn verify_lut<T: Sample + PartialEq>(
+    uploader: &ThreadUploader,
+    texture: &TextureDescription,
+    data: &[T],
+) -> anyhow::Result<()> {
+    let readback: Vec<T> = uploader.read_image(texture)?;
+    if readback.get(..data.len()) != Some(data) {
+        anyhow::bail!("The LUT read back from the GPU differs from the uploaded one");
     }
-    data
+    Ok(())
 }
 fn create_from_element(
     element: gst::Element,
     channel: BidirChannel<GstRenderMessage>,
-) -> GstRenderStruct {
+) -> error::Result<GstRenderStruct> {
     // We assume the element has a 'context' property which is the GLContext
     let ctx = element
         .get_property("context")
@@ -84,6 +106,11 @@ fn setup_filterapp(filterapp: gst::Element, channel: BidirChannel<GstRenderMessa
             let renderer = match *renderer {
                 Some(ref r) => r,
                 None => {
+                    // A previous attempt failed and used up the channel, don't try again.
+                    let channel = match channel.lock().unwrap().take() {
+                        Some(channel) => channel,
+                        None => return Some(Value::from(&false)),
+                    };
                     let filter_element = _vals[0]
                         .get::<gst::Element>()
                         .expect("Failed to get Element")
@@ -92,14 +119,13 @@ fn setup_filterapp(filterapp: gst::Element, channel: BidirChannel<GstRenderMessa
                     println!("Name of element: {}", &name);
                     // UGLY HACK: The closure is Send + Sync, which means we can't use the Receiver
                     // but we want to move it into GstRenderStruct.
-                    let channel = channel
-                        .lock()
-                        .unwrap()
-                        .take()
-                        .expect("Can only cretae GstRenderStruct once");
-
-                    *renderer = Some(create_from_element(filter_element, channel));
-                    renderer.as_ref().unwrap()
+                    match create_from_element(filter_element, channel) {
+                        Ok(r) => renderer.get_or_insert(r),
+                        Err(e) => {
+                            println!("Failed to create renderer: {}", e);
+                            return Some(Value::from(&false));
+                        }
+                    }
                 }
             };
 
@@ -109,7 +135,10 @@ fn setup_filterapp(filterapp: gst::Element, channel: BidirChannel<GstRenderMessa
 
             println!("Got draw signal: {} ms", el);
 
-            unsafe { renderer.draw() };
+            if let Err(e) = unsafe { renderer.draw() } {
+                println!("Failed to draw: {}", e);
+                return Some(Value::from(&false));
+            }
 
             Some(Value::from(&true))
         })
@@ -119,7 +148,11 @@ fn setup_filterapp(filterapp: gst::Element, channel: BidirChannel<GstRenderMessa
 const TX_WIDTH: u32 = 1;
 const TX_HEIGHT: u32 = 1;
 const BUF_SIZE: usize = (TX_WIDTH * TX_HEIGHT * 4) as usize; // Size of one buffer (Assuming 4 channels RGBA)
-const FPS: u32 = 5;
+
+// Shows the image in grey, e.g. `Some(lut::Palette::hot_iron)` for pseudo-colour display
+const PALETTE: Option<fn() -> lut::Palette> = None;
+// Frames are rendered at the rate of the cine playback, so each cine frame is shown once
+const FPS: u32 = 15;
 
 fn setup_appsrc(appsrc: &gst_app::AppSrc) {
     let video_info =
@@ -134,13 +167,31 @@ fn setup_appsrc(appsrc: &gst_app::AppSrc) {
     ));
 }
 
+// Running time of the pipeline, `None` until it has a clock.
+fn running_time(pipeline: &gst::Pipeline) -> Option<Duration> {
+    let now = pipeline.get_clock()?.get_time().nseconds()?;
+    let base = pipeline.get_base_time().nseconds()?;
+    Some(Duration::from_nanos(now.saturating_sub(base)))
+}
+
 fn set_sync_bus_handler(bus: &gst::Bus, shared_context: gst_gl::GLContext) {
+    // The pipeline has to use the display of the shared context, otherwise
+    // EGL/GLX will refuse to share with contexts created on another display.
+    let gl_display = shared_context.get_display();
     #[allow(clippy::single_match)]
     bus.set_sync_handler(move |_, msg| {
         match msg.view() {
             gst::MessageView::NeedContext(ctxt) => {
                 println!("Got context message");
                 let context_type = ctxt.get_context_type();
+                if context_type == *gst_gl::GL_DISPLAY_CONTEXT_TYPE {
+                    if let Some(el) = msg.get_src().map(|s| s.downcast::<gst::Element>().unwrap()) {
+                        println!("Display context");
+                        let context = gst::Context::new(context_type, true);
+                        context.set_gl_display(&gl_display);
+                        el.set_context(&context);
+                    }
+                }
                 if context_type == "gst.gl.app_context" {
                     if let Some(el) = msg.get_src().map(|s| s.downcast::<gst::Element>().unwrap()) {
                         println!("App context");
@@ -161,7 +212,7 @@ fn set_sync_bus_handler(bus: &gst::Bus, shared_context: gst_gl::GLContext) {
     });
 }
 
-fn main() {
+fn main() -> anyhow::Result<()> {
     gst::init().expect("GStreamer is installed");
     // let pipeline =
     //     gst::parse_launch("videotestsrc ! glupload ! glfilterapp name=filterapp ! glimagesink")
@@ -179,8 +230,15 @@ fn main() {
         .expect("Should be a pipeline element");
     let bus = pipeline.get_bus().expect("Bus is present");
 
-    let uploader = ThreadUploader::new();
-    let shared_context = uploader.get_shared_context();
+    // Zoom::Fit shrinks the image a lot, mipmaps keep it from aliasing
+    let uploader = ThreadUploader::with_config(UploaderConfig {
+        backend: HeadlessBackend::from_env()?,
+        mipmaps: true,
+        ..Default::default()
+    })?;
+    let shared_context = uploader.get_shared_context()?.ok_or_else(|| {
+        anyhow::anyhow!("The headless backend has no context to share with GStreamer")
+    })?;
     set_sync_bus_handler(&bus, shared_context);
     println!("Context sharing setup");
 
@@ -214,62 +272,122 @@ fn main() {
         }
     }
     // Signal that the uploader can set its context as current
-    uploader.set_current();
+    uploader.set_current()?;
 
     pipeline
         .set_state(gst::State::Playing)
         .expect("Pipeline should be playable");
 
-    let mut last_time = std::time::Instant::now();
-    let target_sleep = 1000 / FPS;
-
-    let mut q = Quad::with_init((256_f32, 256_f32));
     let mut state = ViewState::new();
     state.update_magnification(0.5);
-    let image_texture = uploader
-        .acquire_image_handle((IMAGE_WIDTH, IMAGE_HEIGHT))
-        .expect("Failed to acquire image texture");
-    let lut_texture = uploader
-        .acquire_lut_handle()
-        .expect("Failed to acquire lut texture");
+    let image = uploader
+        .acquire_tiled_image(PixelFormat::R16, (IMAGE_WIDTH, IMAGE_HEIGHT))?
+        .expect("Failed to acquire image textures");
+    // All tiles share the format of the image
+    let program = ImageProgram::for_format(image.tiles[0].texture.format());
 
     // This simulates that we actually should load new texture data
     let image_data = generate_texture_data(1.0);
     let lut_data = generate_lut_data();
-    uploader.load_image(&image_texture, (IMAGE_WIDTH, IMAGE_HEIGHT), image_data);
-    uploader.load_lut(&lut_texture, lut_data);
-    q.map_texture_coords(
-        (IMAGE_WIDTH as f32, IMAGE_HEIGHT as f32),
-        (
-            image_texture.handle.width as f32,
-            image_texture.handle.height as f32,
-        ),
-    );
-
-    uploader.flush();
-
+    let mut uploads = uploader.load_tiled_image(&image, &image_data)?;
+    let lut_texture = match PALETTE {
+        // The grey LUT coloured by the palette, in a texture of its own format
+        Some(palette) => {
+            let texture = uploader
+                .acquire_palette_lut_handle()?
+                .expect("Failed to acquire palette lut texture");
+            let colors = palette().apply(&lut_data);
+            uploads.push(uploader.load_palette_lut(&texture, colors.clone())?);
+            verify_lut(&uploader, &texture, &colors)?;
+            texture
+        }
+        None => {
+            let texture = uploader
+                .acquire_lut_handle()?
+                .expect("Failed to acquire lut texture");
+            uploads.push(uploader.load_lut(&texture, lut_data.clone())?);
+            verify_lut(&uploader, &texture, &lut_data)?;
+            texture
+        }
+    };
+    // One quad per tile, all positioned in the space of the full image
+    let quads: Vec<_> = image
+        .tiles
+        .iter()
+        .map(|tile| {
+            let layout = &tile.layout;
+            let mut q = Quad::with_init((256_f32, 256_f32));
+            q.map_tile_coords(
+                (IMAGE_WIDTH as f32, IMAGE_HEIGHT as f32),
+                (
+                    (layout.region_offset.0 as f32, layout.region_offset.1 as f32),
+                    (layout.region_size.0 as f32, layout.region_size.1 as f32),
+                ),
+                (
+                    layout.texture_offset.0 as f32,
+                    layout.texture_offset.1 as f32,
+                ),
+                (
+                    tile.texture.handle.width as f32,
+                    tile.texture.handle.height as f32,
+                ),
+            );
+            q
+        })
+        .collect();
+
+    uploader.flush()?;
+
+    // Frames follow the pipeline clock, slow rendering drops frames instead of slowing down
+    let frame_count = image
+        .tiles
+        .iter()
+        .map(|t| t.texture.frames())
+        .min()
+        .unwrap_or(1);
+    let mut cine = CineController::new(frame_count, FPS as f64)?;
+    cine.set_mode(Duration::default(), PlaybackMode::Bounce);
+
+    // Render times are scheduled ahead, a late frame does not push back the next ones
+    let frame_interval = Duration::from_secs_f64(1.0 / FPS as f64);
+    let mut next_frame = std::time::Instant::now();
     'main_loop: loop {
-        let now = std::time::Instant::now();
-        let sleep_time = target_sleep as i32 - (now - last_time).as_millis() as i32;
-        println!("Sleeping for: {}", sleep_time);
-        let sleep_time = sleep_time.max(0) as u64;
-        // std::thread::sleep(Duration::from_millis(sleep_time));
-        spin_sleep::sleep(Duration::from_millis(sleep_time));
-        last_time = std::time::Instant::now();
+        spin_sleep::sleep(next_frame.saturating_duration_since(std::time::Instant::now()));
+        next_frame += frame_interval;
+
+        if let Some(now) = running_time(&pipeline) {
+            // Start once the pipeline has a clock
+            if state.frame.is_none() {
+                cine.play(now);
+            }
+            cine.update(now, &mut state);
+        }
 
         // Try to get a texture to use for upload
 
         // Remap the texture coordinates if we have changed texture size
-        let vertex_data = q.get_vertex(&state);
+        let image_quads = image
+            .tiles
+            .iter()
+            .zip(quads.iter())
+            .map(|(tile, q)| ImageQuad {
+                texture: tile.texture.clone(),
+                vertex_data: q.get_vertex(&state),
+            })
+            .collect();
 
         // Simulate the upload of the image texture.
-        channel
-            .send(GstRenderMessage {
-                image_texture: image_texture.clone(),
-                lut_texture: lut_texture.clone(),
-                vertex_data,
-            })
-            .expect("Failed to send textures");
+        let message = GstRenderMessage {
+            image_quads,
+            lut_texture: Some(lut_texture.clone()),
+            program,
+            uploads: uploads.clone(),
+            view_state: state,
+        };
+        if channel.send(message).is_err() {
+            println!("The renderer is gone");
+            break 'main_loop;
+        }
 
         // Create a "fake" buffer and send down the pipeline
         let mut buffer = gst::Buffer::with_size(BUF_SIZE).expect("Failed to allocate new buffer");
@@ -299,8 +417,14 @@ fn main() {
             }
         }
     }
+    if cine.dropped_frames() > 0 {
+        println!("Dropped {} cine frames", cine.dropped_frames());
+    }
     pipeline.send_event(gst::event::Eos::new());
     pipeline
         .set_state(gst::State::Null)
         .expect("Deallocating pipeline");
+    // Frees the textures and joins the uploader thread, fails if the thread had died.
+    uploader.shutdown()?;
+    Ok(())
 }
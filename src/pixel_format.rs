@@ -0,0 +1,219 @@
+use crate::rendergl::bindings::gl;
+use std::ffi::c_void;
+
+/// Storage format of an image texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+    /// 8-bit unsigned mono
+    R8,
+    /// 16-bit unsigned mono
+    R16,
+    /// 16-bit signed mono (e.g. CT Hounsfield units)
+    R16S,
+    /// 32-bit float mono
+    R32F,
+    Rgb8,
+    Rgba8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleType {
+    U8,
+    U16,
+    I16,
+    F32,
+}
+
+impl PixelFormat {
+    pub fn channels(&self) -> usize {
+        match self {
+            PixelFormat::Rgb8 => 3,
+            PixelFormat::Rgba8 => 4,
+            _ => 1,
+        }
+    }
+
+    pub fn sample_type(&self) -> SampleType {
+        match self {
+            PixelFormat::R8 | PixelFormat::Rgb8 | PixelFormat::Rgba8 => SampleType::U8,
+            PixelFormat::R16 => SampleType::U16,
+            PixelFormat::R16S => SampleType::I16,
+            PixelFormat::R32F => SampleType::F32,
+        }
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.channels() * self.sample_type().size()
+    }
+
+    /// Number of samples needed for an image of the given size.
+    pub fn sample_count(&self, size: (usize, usize)) -> usize {
+        size.0 * size.1 * self.channels()
+    }
+
+    pub fn gl_internal_format(&self) -> gl::types::GLenum {
+        match self {
+            PixelFormat::R8 => gl::R8,
+            PixelFormat::R16 => gl::R16,
+            // Normalized to [-1, 1] so the texture can still be filtered.
+            PixelFormat::R16S => gl::R16_SNORM,
+            PixelFormat::R32F => gl::R32F,
+            PixelFormat::Rgb8 => gl::RGB8,
+            PixelFormat::Rgba8 => gl::RGBA8,
+        }
+    }
+
+    pub fn gl_format(&self) -> gl::types::GLenum {
+        match self {
+            PixelFormat::Rgb8 => gl::RGB,
+            PixelFormat::Rgba8 => gl::RGBA,
+            _ => gl::RED,
+        }
+    }
+
+    pub fn gl_type(&self) -> gl::types::GLenum {
+        match self.sample_type() {
+            SampleType::U8 => gl::UNSIGNED_BYTE,
+            SampleType::U16 => gl::UNSIGNED_SHORT,
+            SampleType::I16 => gl::SHORT,
+            SampleType::F32 => gl::FLOAT,
+        }
+    }
+}
+
+impl SampleType {
+    pub fn size(&self) -> usize {
+        match self {
+            SampleType::U8 => 1,
+            SampleType::U16 | SampleType::I16 => 2,
+            SampleType::F32 => 4,
+        }
+    }
+}
+
+/// Image data on its way to the uploader thread.
+#[derive(Debug)]
+pub enum SampleData {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    I16(Vec<i16>),
+    F32(Vec<f32>),
+}
+
+impl SampleData {
+    /// `len` samples of the given type, all zero.
+    pub fn zeroed(sample_type: SampleType, len: usize) -> Self {
+        match sample_type {
+            SampleType::U8 => SampleData::U8(vec![0; len]),
+            SampleType::U16 => SampleData::U16(vec![0; len]),
+            SampleType::I16 => SampleData::I16(vec![0; len]),
+            SampleType::F32 => SampleData::F32(vec![0.0; len]),
+        }
+    }
+
+    pub fn sample_type(&self) -> SampleType {
+        match self {
+            SampleData::U8(_) => SampleType::U8,
+            SampleData::U16(_) => SampleType::U16,
+            SampleData::I16(_) => SampleType::I16,
+            SampleData::F32(_) => SampleType::F32,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            SampleData::U8(d) => d.len(),
+            SampleData::U16(d) => d.len(),
+            SampleData::I16(d) => d.len(),
+            SampleData::F32(d) => d.len(),
+        }
+    }
+
+    pub fn byte_len(&self) -> usize {
+        self.len() * self.sample_type().size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Pointer to the sample with the given index, for passing to GL.
+    pub fn sample_ptr(&self, index: usize) -> *const c_void {
+        match self {
+            SampleData::U8(d) => d[index..].as_ptr() as _,
+            SampleData::U16(d) => d[index..].as_ptr() as _,
+            SampleData::I16(d) => d[index..].as_ptr() as _,
+            SampleData::F32(d) => d[index..].as_ptr() as _,
+        }
+    }
+
+    /// Pointer to the first sample, for GL to write to.
+    pub fn as_mut_ptr(&mut self) -> *mut c_void {
+        match self {
+            SampleData::U8(d) => d.as_mut_ptr() as _,
+            SampleData::U16(d) => d.as_mut_ptr() as _,
+            SampleData::I16(d) => d.as_mut_ptr() as _,
+            SampleData::F32(d) => d.as_mut_ptr() as _,
+        }
+    }
+}
+
+/// Types that can be uploaded as texture samples.
+pub trait Sample: Copy + Send + 'static {
+    const SAMPLE_TYPE: SampleType;
+    fn into_data(data: Vec<Self>) -> SampleData;
+    /// The samples, if the data is of this type.
+    fn from_data(data: SampleData) -> Option<Vec<Self>>;
+}
+
+impl Sample for u8 {
+    const SAMPLE_TYPE: SampleType = SampleType::U8;
+    fn into_data(data: Vec<Self>) -> SampleData {
+        SampleData::U8(data)
+    }
+    fn from_data(data: SampleData) -> Option<Vec<Self>> {
+        match data {
+            SampleData::U8(data) => Some(data),
+            _ => None,
+        }
+    }
+}
+
+impl Sample for u16 {
+    const SAMPLE_TYPE: SampleType = SampleType::U16;
+    fn into_data(data: Vec<Self>) -> SampleData {
+        SampleData::U16(data)
+    }
+    fn from_data(data: SampleData) -> Option<Vec<Self>> {
+        match data {
+            SampleData::U16(data) => Some(data),
+            _ => None,
+        }
+    }
+}
+
+impl Sample for i16 {
+    const SAMPLE_TYPE: SampleType = SampleType::I16;
+    fn into_data(data: Vec<Self>) -> SampleData {
+        SampleData::I16(data)
+    }
+    fn from_data(data: SampleData) -> Option<Vec<Self>> {
+        match data {
+            SampleData::I16(data) => Some(data),
+            _ => None,
+        }
+    }
+}
+
+impl Sample for f32 {
+    const SAMPLE_TYPE: SampleType = SampleType::F32;
+    fn into_data(data: Vec<Self>) -> SampleData {
+        SampleData::F32(data)
+    }
+    fn from_data(data: SampleData) -> Option<Vec<Self>> {
+        match data {
+            SampleData::F32(data) => Some(data),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,127 @@
+/// A rectangle of texels, rows counted from the start of the image data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub offset: (usize, usize),
+    pub size: (usize, usize),
+}
+
+impl Region {
+    pub fn new(offset: (usize, usize), size: (usize, usize)) -> Self {
+        Self { offset, size }
+    }
+
+    pub fn end(&self) -> (usize, usize) {
+        (self.offset.0 + self.size.0, self.offset.1 + self.size.1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size.0 == 0 || self.size.1 == 0
+    }
+
+    pub fn contains(&self, other: &Region) -> bool {
+        let (end, other_end) = (self.end(), other.end());
+        self.offset.0 <= other.offset.0
+            && self.offset.1 <= other.offset.1
+            && other_end.0 <= end.0
+            && other_end.1 <= end.1
+    }
+
+    // The union if it is a rectangle, i.e. the regions line up along one axis and
+    // overlap or touch along the other.
+    fn merge(&self, other: &Region) -> Option<Region> {
+        let (end, other_end) = (self.end(), other.end());
+        let same_columns = self.offset.0 == other.offset.0 && end.0 == other_end.0;
+        let same_rows = self.offset.1 == other.offset.1 && end.1 == other_end.1;
+        let rows_touch = self.offset.1 <= other_end.1 && other.offset.1 <= end.1;
+        let columns_touch = self.offset.0 <= other_end.0 && other.offset.0 <= end.0;
+        if (same_columns && rows_touch) || (same_rows && columns_touch) {
+            let offset = (
+                self.offset.0.min(other.offset.0),
+                self.offset.1.min(other.offset.1),
+            );
+            let end = (end.0.max(other_end.0), end.1.max(other_end.1));
+            Some(Region::new(offset, (end.0 - offset.0, end.1 - offset.1)))
+        } else {
+            None
+        }
+    }
+}
+
+/// The part of a texture that holds loaded data, as a list of rectangles.
+/// Rectangles that line up are merged, so an image loaded line by line or in a
+/// grid of tiles ends up as a single rectangle.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidRegion {
+    rects: Vec<Region>,
+}
+
+impl ValidRegion {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rects(&self) -> &[Region] {
+        &self.rects
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    /// True if a single rectangle contains `region`, regions pieced together from
+    /// rectangles that could not be merged are not detected.
+    pub fn covers(&self, region: &Region) -> bool {
+        self.rects.iter().any(|r| r.contains(region))
+    }
+
+    pub fn add(&mut self, region: Region) {
+        if region.is_empty() || self.covers(&region) {
+            return;
+        }
+        self.rects.retain(|r| !region.contains(r));
+        let mut region = region;
+        // Merging may make the result line up with another rectangle, repeat until stable
+        while let Some(index) = self.rects.iter().position(|r| r.merge(&region).is_some()) {
+            let other = self.rects.swap_remove(index);
+            region = other.merge(&region).unwrap();
+        }
+        self.rects.push(region);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_merge_into_one_rect() {
+        let mut valid = ValidRegion::new();
+        for line in 0..10 {
+            valid.add(Region::new((0, line), (64, 1)));
+        }
+        assert_eq!(valid.rects(), &[Region::new((0, 0), (64, 10))]);
+        assert!(valid.covers(&Region::new((10, 2), (5, 5))));
+        assert!(!valid.covers(&Region::new((0, 0), (64, 11))));
+    }
+
+    #[test]
+    fn tiles_in_any_order_merge() {
+        let mut valid = ValidRegion::new();
+        valid.add(Region::new((16, 16), (16, 16)));
+        valid.add(Region::new((0, 0), (16, 16)));
+        assert_eq!(valid.rects().len(), 2);
+        valid.add(Region::new((16, 0), (16, 16)));
+        valid.add(Region::new((0, 16), (16, 16)));
+        assert_eq!(valid.rects(), &[Region::new((0, 0), (32, 32))]);
+    }
+
+    #[test]
+    fn contained_regions_are_dropped() {
+        let mut valid = ValidRegion::new();
+        valid.add(Region::new((4, 4), (2, 2)));
+        valid.add(Region::new((0, 0), (0, 8)));
+        valid.add(Region::new((0, 0), (8, 8)));
+        valid.add(Region::new((1, 1), (3, 3)));
+        assert_eq!(valid.rects(), &[Region::new((0, 0), (8, 8))]);
+    }
+}
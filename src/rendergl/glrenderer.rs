@@ -1,21 +1,244 @@
-use super::{bindings::gl, vertex};
+use super::{
+    bindings::gl,
+    shader::{ShaderError, ShaderSource, ShaderStage, ShaderType},
+    vertex,
+    view_state::{Interpolation, MinFilter, ViewState},
+};
+use crate::{
+    error::{Error, Result},
+    lut::UniformWindow,
+    pixel_format::PixelFormat,
+    texture_handle::GlTexture,
+};
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     ffi::{c_void, CString},
     mem, ptr,
 };
 use vertex::Quad;
 
+/// Per-channel (red, green, blue) adjustment of colour images. Contrast scales around
+/// mid grey, brightness is added after, both in normalized units.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ColorAdjustment {
+    pub brightness: [f32; 3],
+    pub contrast: [f32; 3],
+}
+
+impl Default for ColorAdjustment {
+    fn default() -> Self {
+        Self {
+            brightness: [0.0; 3],
+            contrast: [1.0; 3],
+        }
+    }
+}
+
+/// How single channel images are mapped to the LUT.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MonoSettings {
+    /// The float values at the first and the last LUT entry, the LUT is interpolated in
+    /// between. Integer images index the LUT with their stored values instead.
+    pub float_range: (f32, f32),
+    /// Applies a linear window in the shader and skips the LUT texture, for fast
+    /// window/level changes. Float images are windowed on their values. Signed images
+    /// loaded as R16 are sign extended after sampling, so they are drawn without
+    /// filtering, load them as R16S to have them filtered.
+    #[serde(default)]
+    pub window: Option<UniformWindow>,
+}
+
+impl MonoSettings {
+    /// The float range has to span some values, or the samples can not be spread over
+    /// the LUT.
+    pub fn validate(&self) -> Result<()> {
+        let (min, max) = self.float_range;
+        if min.is_finite() && max.is_finite() && min != max {
+            Ok(())
+        } else {
+            Err(Error::InvalidSettings(format!(
+                "Float range from {} to {}",
+                min, max
+            )))
+        }
+    }
+}
+
+impl Default for MonoSettings {
+    fn default() -> Self {
+        Self {
+            float_range: (0.0, 1.0),
+            window: None,
+        }
+    }
+}
+
+/// The program to draw an image with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageProgram {
+    /// Single channel images, shown through the LUT.
+    Mono(MonoSettings),
+    /// RGB(A) images, shown as stored apart from the adjustment.
+    Argb(ColorAdjustment),
+}
+
+impl ImageProgram {
+    /// The program for textures of the format, colour images are not adjusted.
+    pub fn for_format(format: PixelFormat) -> Self {
+        match format.channels() {
+            1 => ImageProgram::Mono(MonoSettings::default()),
+            _ => ImageProgram::Argb(ColorAdjustment::default()),
+        }
+    }
+
+    /// Whether the program draws through the LUT texture.
+    pub fn reads_lut(&self) -> bool {
+        matches!(self, ImageProgram::Mono(settings) if settings.window.is_none())
+    }
+
+    /// Mono draws single channel textures, ARGB colour ones.
+    pub fn check_format(&self, format: PixelFormat) -> Result<()> {
+        match (self, format.channels()) {
+            (ImageProgram::Mono(_), 1) => Ok(()),
+            (ImageProgram::Argb(_), channels) if channels > 1 => Ok(()),
+            (ImageProgram::Mono(_), _) => Err(Error::ProgramMismatch("mono", format)),
+            (ImageProgram::Argb(_), _) => Err(Error::ProgramMismatch("ARGB", format)),
+        }
+    }
+}
+
+/// Scale and offset from the sampled, normalized value of a mono texture to the LUT
+/// index (integer formats) or to [0, 1] over the LUT (float formats). With a uniform
+/// window it is the stored value for all formats.
+pub fn sample_mapping(format: PixelFormat, settings: &MonoSettings) -> (f32, f32) {
+    match format {
+        PixelFormat::R16 => (u16::MAX as f32, 0.0),
+        // SNORM is stored / 32767, so -32768 reads as -32767
+        PixelFormat::R16S => (i16::MAX as f32, 0.0),
+        PixelFormat::R32F if settings.window.is_some() => (1.0, 0.0),
+        PixelFormat::R32F => {
+            let (min, max) = settings.float_range;
+            let scale = 1.0 / (max - min);
+            (scale, -min * scale)
+        }
+        PixelFormat::R8 | PixelFormat::Rgb8 | PixelFormat::Rgba8 => (u8::MAX as f32, 0.0),
+    }
+}
+
+// Filtering signed values loaded as R16 would mix the two's complement of negative
+// values with positive ones before the shader sign extends them.
+fn filters_samples(format: PixelFormat, settings: &MonoSettings) -> bool {
+    !(format == PixelFormat::R16 && settings.window.map_or(false, |w| w.bit_depth.signed))
+}
+
+// Selects the variant of the mono fragment shader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct MonoVariant {
+    palette: bool,
+    float_samples: bool,
+    // The LUT texture is not read
+    uniform_window: bool,
+    bicubic: bool,
+}
+
+impl MonoVariant {
+    fn new(
+        image_format: PixelFormat,
+        lut_format: Option<PixelFormat>,
+        settings: &MonoSettings,
+        interpolation: Interpolation,
+    ) -> Self {
+        let uniform_window = settings.window.is_some();
+        Self {
+            palette: lut_format.map_or(false, |format| format.channels() > 1) && !uniform_window,
+            float_samples: image_format == PixelFormat::R32F,
+            uniform_window,
+            bicubic: interpolation == Interpolation::Bicubic
+                && filters_samples(image_format, settings),
+        }
+    }
+
+    // Every variant `new` can select, the palette is not read with a uniform window.
+    fn all() -> Vec<Self> {
+        let flags = [false, true];
+        let mut variants = Vec::new();
+        for &palette in &flags {
+            for &float_samples in &flags {
+                for &uniform_window in &flags {
+                    for &bicubic in &flags {
+                        if palette && uniform_window {
+                            continue;
+                        }
+                        variants.push(Self {
+                            palette,
+                            float_samples,
+                            uniform_window,
+                            bicubic,
+                        });
+                    }
+                }
+            }
+        }
+        variants
+    }
+
+    fn defines(&self) -> Vec<&'static str> {
+        let mut defines = Vec::new();
+        if self.uniform_window {
+            defines.push("UNIFORM_WINDOW");
+        }
+        if self.palette {
+            defines.push("PALETTE_LUT");
+        }
+        if self.float_samples {
+            defines.push("FLOAT_SAMPLES");
+        }
+        if self.bicubic {
+            defines.push("BICUBIC");
+        }
+        defines
+    }
+}
+
 pub struct GlRenderer {
     bindings: gl::Gl,
     vao: u32,
     quad_vertex_buffer: u32,
     quad_index_buffer: u32,
-    program_mono: u32,
+    // One program per variant of the mono fragment shader
+    programs_mono: HashMap<MonoVariant, u32>,
     program_argb: u32,
+    // Sampler objects for the image texture, override the filtering set on the textures
+    samplers: HashMap<(Interpolation, MinFilter), u32>,
+    // For images whose samples must not be filtered, see `filters_samples`
+    nearest_sampler: u32,
 }
 
 impl GlRenderer {
-    pub fn new<F>(func: F) -> Self
+    const VERTEX_SHADER: ShaderSource<'static> =
+        ShaderSource::new("glvert.glsl", include_str!("shaders/glvert.glsl"));
+    const FRAGMENT_SHADER: ShaderSource<'static> =
+        ShaderSource::new("glfrag.glsl", include_str!("shaders/glfrag.glsl"));
+    const FRAGMENT_SHADER_ARGB: ShaderSource<'static> =
+        ShaderSource::new("glfrag_argb.glsl", include_str!("shaders/glfrag_argb.glsl"));
+    // Uniform locations of the fragment shader
+    const PLACEHOLDER_LOCATION: i32 = 0;
+    const LAYER_LOCATION: i32 = 1;
+    // Only in the mono program
+    const SAMPLE_SCALE_LOCATION: i32 = 2;
+    const SAMPLE_OFFSET_LOCATION: i32 = 3;
+    // Only with a uniform window
+    const WINDOW_LOCATION: i32 = 4;
+    const RESCALE_LOCATION: i32 = 5;
+    const STORED_RANGE_LOCATION: i32 = 6;
+    const INVERT_LOCATION: i32 = 7;
+    const SIGN_EXTEND_LOCATION: i32 = 8;
+    // Only in the ARGB program
+    const BRIGHTNESS_LOCATION: i32 = 2;
+    const CONTRAST_LOCATION: i32 = 3;
+
+    pub fn new<F>(func: F) -> Result<Self>
     where
         F: FnMut(&'static str) -> *const c_void,
     {
@@ -23,61 +246,202 @@ impl GlRenderer {
         unsafe { Self::create(bindings) }
     }
 
-    unsafe fn create(bindings: gl::Gl) -> Self {
-        let program_mono = Self::compile_program(
-            &bindings,
-            include_str!("shaders/glvert.glsl"),
-            include_str!("shaders/glfrag.glsl"),
-        );
-        let program_argb = 0_u32; // TODO: Create this program.
+    unsafe fn create(bindings: gl::Gl) -> Result<Self> {
+        // Shader errors show up at startup, not at the first draw
+        let mut programs_mono = HashMap::new();
+        let mut compile_mono = || {
+            for variant in MonoVariant::all() {
+                let program = Self::compile_program(
+                    &bindings,
+                    &Self::VERTEX_SHADER,
+                    &Self::FRAGMENT_SHADER,
+                    &variant.defines(),
+                )?;
+                programs_mono.insert(variant, program);
+            }
+            Self::compile_program(
+                &bindings,
+                &Self::VERTEX_SHADER,
+                &Self::FRAGMENT_SHADER_ARGB,
+                &[],
+            )
+        };
+        let program_argb = match compile_mono() {
+            Ok(program) => program,
+            Err(e) => {
+                for program in programs_mono.values() {
+                    bindings.DeleteProgram(*program);
+                }
+                return Err(e);
+            }
+        };
         let (vao, quad_vertex_buffer, quad_index_buffer) = Self::create_vao(&bindings);
-        Self {
+        let mut samplers = HashMap::new();
+        for &interpolation in &[
+            Interpolation::Nearest,
+            Interpolation::Bilinear,
+            Interpolation::Bicubic,
+        ] {
+            for &min_filter in &[MinFilter::Linear, MinFilter::Trilinear] {
+                let min_filter_gl = match min_filter {
+                    MinFilter::Linear => gl::LINEAR,
+                    MinFilter::Trilinear => gl::LINEAR_MIPMAP_LINEAR,
+                };
+                // The bicubic shader path fetches the texels itself, the sampler is only
+                // used when the image is minified
+                let mag_filter = match interpolation {
+                    Interpolation::Nearest => gl::NEAREST,
+                    Interpolation::Bilinear | Interpolation::Bicubic => gl::LINEAR,
+                };
+                let sampler = Self::create_sampler(&bindings, min_filter_gl, mag_filter);
+                samplers.insert((interpolation, min_filter), sampler);
+            }
+        }
+        let nearest_sampler = Self::create_sampler(&bindings, gl::NEAREST, gl::NEAREST);
+        Ok(Self {
             bindings,
             vao,
             quad_vertex_buffer,
             quad_index_buffer,
-            program_mono,
+            programs_mono,
             program_argb,
+            samplers,
+            nearest_sampler,
+        })
+    }
+
+    unsafe fn create_sampler(bindings: &gl::Gl, min_filter: u32, mag_filter: u32) -> u32 {
+        let mut sampler = 0;
+        bindings.CreateSamplers(1, &mut sampler);
+        bindings.SamplerParameteri(sampler, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as _);
+        bindings.SamplerParameteri(sampler, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as _);
+        bindings.SamplerParameteri(sampler, gl::TEXTURE_MIN_FILTER, min_filter as _);
+        bindings.SamplerParameteri(sampler, gl::TEXTURE_MAG_FILTER, mag_filter as _);
+        sampler
+    }
+
+    fn sampler(&self, view_state: &ViewState, filtered: bool) -> u32 {
+        match filtered {
+            true => self.samplers[&(view_state.interpolation, view_state.min_filter)],
+            false => self.nearest_sampler,
         }
     }
 
-    unsafe fn compile_program(bindings: &gl::Gl, vs_src: &str, fs_src: &str) -> u32 {
-        let vs = Self::compile_shader(bindings, vs_src, gl::VERTEX_SHADER);
-        let fs = Self::compile_shader(bindings, fs_src, gl::FRAGMENT_SHADER);
+    // The defines select a variant of the fragment shader.
+    unsafe fn compile_program(
+        bindings: &gl::Gl,
+        vs_src: &ShaderSource,
+        fs_src: &ShaderSource,
+        defines: &[&str],
+    ) -> Result<u32> {
+        let vs = Self::compile_shader(bindings, vs_src, ShaderType::Vertex, &[])?;
+        let fs = match Self::compile_shader(bindings, fs_src, ShaderType::Fragment, defines) {
+            Ok(fs) => fs,
+            Err(e) => {
+                bindings.DeleteShader(vs);
+                return Err(e);
+            }
+        };
 
         let program = bindings.CreateProgram();
         bindings.AttachShader(program, vs);
         bindings.AttachShader(program, fs);
         bindings.LinkProgram(program);
 
-        {
-            let mut success: gl::types::GLint = 1;
-            bindings.GetProgramiv(program, gl::LINK_STATUS, &mut success);
-            assert!(success != 0);
-        }
+        let mut success: gl::types::GLint = 1;
+        bindings.GetProgramiv(program, gl::LINK_STATUS, &mut success);
         bindings.DetachShader(program, vs);
         bindings.DeleteShader(vs);
         bindings.DetachShader(program, fs);
         bindings.DeleteShader(fs);
-        program
+        if success == 0 {
+            let log = Self::info_log(
+                bindings,
+                program,
+                gl::Gl::GetProgramiv,
+                gl::Gl::GetProgramInfoLog,
+            );
+            bindings.DeleteProgram(program);
+            let name = format!("{} + {}", vs_src.name, fs_src.name);
+            return Err(Error::Shader(ShaderError::from_log(
+                ShaderStage::Link,
+                &name,
+                &log,
+                None,
+            )));
+        }
+        Ok(program)
     }
 
     unsafe fn compile_shader(
         bindings: &gl::Gl,
-        src: &str,
-        shader_type: gl::types::GLenum,
-    ) -> u32 {
+        src: &ShaderSource,
+        shader_type: ShaderType,
+        defines: &[&str],
+    ) -> Result<u32> {
+        let stage = ShaderStage::from(shader_type);
+        let shader_type = match shader_type {
+            ShaderType::Vertex => gl::VERTEX_SHADER,
+            ShaderType::Fragment => gl::FRAGMENT_SHADER,
+        };
+        // Interior nul bytes can't be passed to GL
+        let shader_src = CString::new(src.with_defines(defines)).map_err(|e| {
+            let log = format!("Source contains a nul byte at offset {}", e.nul_position());
+            Error::Shader(ShaderError::from_log(stage, src.name, &log, None))
+        })?;
         let shader = bindings.CreateShader(shader_type);
-        let shader_src = CString::new(src).expect("Failed to include vertex shader source");
         // bindings.ShaderSource(vs, 1, [VS_SRC.as_ptr() as *const _].as_ptr(), ptr::null());
         bindings.ShaderSource(shader, 1, [shader_src.as_ptr() as _].as_ptr(), ptr::null());
         bindings.CompileShader(shader);
-        {
-            let mut success: gl::types::GLint = 1;
-            bindings.GetShaderiv(shader, gl::COMPILE_STATUS, &mut success);
-            assert!(success != 0);
+        let mut success: gl::types::GLint = 1;
+        bindings.GetShaderiv(shader, gl::COMPILE_STATUS, &mut success);
+        if success == 0 {
+            let log = Self::info_log(
+                bindings,
+                shader,
+                gl::Gl::GetShaderiv,
+                gl::Gl::GetShaderInfoLog,
+            );
+            bindings.DeleteShader(shader);
+            return Err(Error::Shader(ShaderError::from_log(
+                stage,
+                src.name,
+                &log,
+                Some(src.text),
+            )));
+        }
+        Ok(shader)
+    }
+
+    // Reads the info log of a shader or program, given the matching pair of GL getters.
+    unsafe fn info_log(
+        bindings: &gl::Gl,
+        object: u32,
+        get_param: unsafe fn(&gl::Gl, u32, gl::types::GLenum, *mut gl::types::GLint),
+        get_log: unsafe fn(
+            &gl::Gl,
+            u32,
+            gl::types::GLsizei,
+            *mut gl::types::GLsizei,
+            *mut gl::types::GLchar,
+        ),
+    ) -> String {
+        let mut length: gl::types::GLint = 0;
+        get_param(bindings, object, gl::INFO_LOG_LENGTH, &mut length);
+        if length <= 0 {
+            return String::new();
         }
-        shader
+        let mut log = vec![0_u8; length as usize];
+        let mut written: gl::types::GLsizei = 0;
+        get_log(
+            bindings,
+            object,
+            length,
+            &mut written,
+            log.as_mut_ptr() as _,
+        );
+        log.truncate(written.max(0) as usize);
+        String::from_utf8_lossy(&log).into_owned()
     }
     unsafe fn create_vao(bindings: &gl::Gl) -> (u32, u32, u32) {
         // Generate Vertex Array Object, this stores buffers/pointers/indexes
@@ -157,39 +521,369 @@ impl GlRenderer {
 
         self.bindings.BindBuffer(gl::ARRAY_BUFFER, 0);
     }
-    unsafe fn draw_image(&self, vertices: &[vertex::Vertex], image_texture: u32, lut_texture: u32) {
-        // Update the vertex buffer
-        self.update_vertex_buffer(vertices);
+    unsafe fn set_window_uniforms(&self, program: u32, window: &UniformWindow) {
+        let (min, max) = window.bit_depth.range();
+        let uniforms = [
+            (
+                Self::WINDOW_LOCATION,
+                window.window.center,
+                window.window.width,
+            ),
+            (Self::RESCALE_LOCATION, window.slope, window.intercept),
+            (Self::STORED_RANGE_LOCATION, min, max),
+        ];
+        for (location, x, y) in uniforms.iter() {
+            self.bindings
+                .ProgramUniform2f(program, *location, *x as f32, *y as f32);
+        }
+        self.bindings
+            .ProgramUniform1i(program, Self::INVERT_LOCATION, window.invert as i32);
+        self.bindings.ProgramUniform1i(
+            program,
+            Self::SIGN_EXTEND_LOCATION,
+            window.bit_depth.signed as i32,
+        );
+    }
 
-        self.bindings.UseProgram(self.program_mono);
+    unsafe fn draw_image(
+        &self,
+        quads: &[(&[vertex::Vertex], &GlTexture)],
+        placeholders: &[&[vertex::Vertex]],
+        lut_texture: Option<&GlTexture>,
+        program: ImageProgram,
+        view_state: &ViewState,
+    ) {
+        let (program, filtered) = match program {
+            ImageProgram::Mono(settings) => {
+                // All quads are tiles of the same image
+                let format = quads.first().map_or(PixelFormat::R16, |(_, t)| t.format);
+                let variant = MonoVariant::new(
+                    format,
+                    lut_texture.map(|lut_texture| lut_texture.format),
+                    &settings,
+                    view_state.interpolation,
+                );
+                let program = self.programs_mono[&variant];
+                let (scale, offset) = sample_mapping(format, &settings);
+                self.bindings
+                    .ProgramUniform1f(program, Self::SAMPLE_SCALE_LOCATION, scale);
+                self.bindings
+                    .ProgramUniform1f(program, Self::SAMPLE_OFFSET_LOCATION, offset);
+                if let Some(window) = settings.window {
+                    self.set_window_uniforms(program, &window);
+                }
+                (program, filters_samples(format, &settings))
+            }
+            ImageProgram::Argb(adjustment) => {
+                self.bindings.ProgramUniform3fv(
+                    self.program_argb,
+                    Self::BRIGHTNESS_LOCATION,
+                    1,
+                    adjustment.brightness.as_ptr(),
+                );
+                self.bindings.ProgramUniform3fv(
+                    self.program_argb,
+                    Self::CONTRAST_LOCATION,
+                    1,
+                    adjustment.contrast.as_ptr(),
+                );
+                (self.program_argb, true)
+            }
+        };
+        self.bindings.UseProgram(program);
         self.bindings.BindVertexArray(self.vao);
+        self.bindings
+            .BindSampler(0, self.sampler(view_state, filtered));
 
-        // Activate and bind the textures
-        self.bindings.ActiveTexture(gl::TEXTURE0); // Activate texture unit 0
-        self.bindings.BindTexture(gl::TEXTURE_2D, image_texture);
-        // self.bindings.BindTexture(gl::TEXTURE_2D, self.texture_id);
-        self.bindings.ActiveTexture(gl::TEXTURE0 + 1);
-        self.bindings.BindTexture(gl::TEXTURE_2D, lut_texture);
-
+        // Parts of the image that are not loaded yet, the loaded parts are drawn on top
+        self.bindings
+            .ProgramUniform1i(program, Self::PLACEHOLDER_LOCATION, 1);
+        for vertices in placeholders {
+            self.update_vertex_buffer(vertices);
+            self.bindings
+                .DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_SHORT, ptr::null());
+        }
         self.bindings
-            .DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_SHORT, ptr::null());
+            .ProgramUniform1i(program, Self::PLACEHOLDER_LOCATION, 0);
+
+        // The LUT is shared by all quads of the image
+        if let Some(lut_texture) = lut_texture {
+            self.bindings.ActiveTexture(gl::TEXTURE0 + 1);
+            self.bindings
+                .BindTexture(gl::TEXTURE_2D_ARRAY, lut_texture.id);
+        }
+
+        // Draw one quad per image texture (tiles of a large image)
+        for (vertices, image_texture) in quads {
+            // Update the vertex buffer
+            self.update_vertex_buffer(vertices);
+
+            self.bindings.ActiveTexture(gl::TEXTURE0); // Activate texture unit 0
+            self.bindings
+                .BindTexture(gl::TEXTURE_2D_ARRAY, image_texture.id);
+            self.bindings.ProgramUniform1i(
+                program,
+                Self::LAYER_LOCATION,
+                Self::layer(image_texture, view_state) as _,
+            );
+
+            self.bindings
+                .DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_SHORT, ptr::null());
+        }
 
         // Unbind resources
+        self.bindings.BindSampler(0, 0);
         self.bindings.BindVertexArray(0);
         self.bindings.ActiveTexture(gl::TEXTURE0); // Activate texture unit 0
-        self.bindings.BindTexture(gl::TEXTURE_2D, 0);
+        self.bindings.BindTexture(gl::TEXTURE_2D_ARRAY, 0);
         self.bindings.ActiveTexture(gl::TEXTURE0 + 1); // Activate texture unit 0
-        self.bindings.BindTexture(gl::TEXTURE_2D, 0);
+        self.bindings.BindTexture(gl::TEXTURE_2D_ARRAY, 0);
         self.bindings.UseProgram(0);
     }
 
-    pub fn draw(&self, vertices: &[vertex::Vertex], image_texture: u32, lut_texture: u32) {
+    /// The layer of the texture holding `ViewState::frame`. Frames past the end show the
+    /// last one, so a single-frame image can share a view state with a cine loop.
+    pub fn layer(texture: &GlTexture, view_state: &ViewState) -> usize {
+        let frame = view_state.frame.unwrap_or(0) as usize;
+        frame.min(texture.layers - 1)
+    }
+
+    /// Makes the GPU wait for a fence from another context before any further commands.
+    ///
+    /// # Safety
+    /// The fence must stay alive until the call returns.
+    pub unsafe fn wait_sync(&self, fence: gl::types::GLsync) {
+        self.bindings.WaitSync(fence, 0, gl::TIMEOUT_IGNORED);
+    }
+
+    /// Draws the image as a list of (vertices, texture) quads, one per tile or loaded part
+    /// of a tile, on top of placeholder quads for the parts that are not loaded.
+    /// The frame and the filtering are taken from the view state.
+    /// The LUT texture is only needed, and only checked, when the program reads it.
+    /// Fails without drawing if any of the textures has been released, or does not suit
+    /// the program.
+    pub fn draw(
+        &self,
+        quads: &[(&[vertex::Vertex], &GlTexture)],
+        placeholders: &[&[vertex::Vertex]],
+        lut_texture: Option<&GlTexture>,
+        program: ImageProgram,
+        view_state: &ViewState,
+    ) -> Result<()> {
+        let lut_texture = match (program.reads_lut(), lut_texture) {
+            (true, None) => return Err(Error::MissingLut),
+            (true, lut_texture) => lut_texture,
+            (false, _) => None,
+        };
+        let textures = quads.iter().map(|(_, texture)| *texture);
+        if let Some(stale) = textures
+            .clone()
+            .chain(lut_texture)
+            .find(|t| !t.is_current())
+        {
+            return Err(Error::StaleTexture(stale.id));
+        }
+        for texture in textures {
+            program.check_format(texture.format)?;
+        }
+        if let ImageProgram::Mono(settings) = &program {
+            settings.validate()?;
+        }
         unsafe {
             self.bindings.ClearColor(1.0, 0.0, 0.0, 1.0);
             self.bindings.Clear(gl::COLOR_BUFFER_BIT);
             // Draw the image
-            self.draw_image(vertices, image_texture, lut_texture);
+            self.draw_image(quads, placeholders, lut_texture, program, view_state);
             // Place to draw the cursor (remember alpha blend)?
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lut::{
+        to_entry, BitDepth, LutPipeline, Modality, Voi, VoiFunction, Window, LUT_ENTRIES,
+    };
+
+    #[test]
+    fn sample_mapping_by_format() {
+        let settings = MonoSettings::default();
+        let index = |format, value: f32| {
+            let (scale, offset) = sample_mapping(format, &settings);
+            value * scale + offset
+        };
+        assert_eq!(index(PixelFormat::R16, 1.0), 65535.0);
+        assert_eq!(index(PixelFormat::R8, 1.0), 255.0);
+        // Signed values index the LUT with their two's complement, see `BitDepth::value`
+        assert_eq!(index(PixelFormat::R16S, -1.0) as i32 as u16, 0x8001);
+
+        let settings = MonoSettings {
+            float_range: (-1.0, 3.0),
+            window: None,
+        };
+        let (scale, offset) = sample_mapping(PixelFormat::R32F, &settings);
+        let position = |value: f32| value * scale + offset;
+        assert_eq!(position(-1.0), 0.0);
+        assert_eq!(position(1.0), 0.5);
+        assert_eq!(position(3.0), 1.0);
+        assert!(settings.validate().is_ok());
+        let settings = MonoSettings {
+            float_range: (2.0, 2.0),
+            window: None,
+        };
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn uniform_window_matches_the_lut_from_samples() {
+        let bit_depth = BitDepth::signed(12).unwrap();
+        let window = Window::new(40.0, 400.0);
+        let mut pipeline = LutPipeline::new(bit_depth);
+        pipeline
+            .set_modality(Modality::Rescale {
+                slope: 0.5,
+                intercept: -20.0,
+            })
+            .unwrap();
+        pipeline
+            .set_voi(Voi::Window {
+                window,
+                function: VoiFunction::Linear,
+            })
+            .unwrap();
+        let table = pipeline.table();
+        let uniform = UniformWindow {
+            slope: 0.5,
+            intercept: -20.0,
+            bit_depth,
+            ..UniformWindow::new(window)
+        };
+        let lut_settings = MonoSettings::default();
+        let uniform_settings = MonoSettings {
+            window: Some(uniform),
+            ..MonoSettings::default()
+        };
+        for stored in i16::MIN..=i16::MAX {
+            // What the sampler returns for the stored value, SNORM reads -32768 as -32767
+            let samples = [
+                (PixelFormat::R16, stored as u16 as f32 / u16::MAX as f32),
+                (
+                    PixelFormat::R16S,
+                    (stored as f32 / i16::MAX as f32).max(-1.0),
+                ),
+            ];
+            for &(format, sample) in samples.iter() {
+                let (scale, offset) = sample_mapping(format, &lut_settings);
+                let index = (sample * scale + offset).round() as i32 as u16;
+                let lut_grey = table[index as usize] as f64 / u16::MAX as f64 * 255.0;
+                let (scale, offset) = sample_mapping(format, &uniform_settings);
+                let uniform_grey = uniform.apply((sample * scale + offset).round()) as f64 * 255.0;
+                assert!(
+                    (lut_grey.round() - uniform_grey.round()).abs() <= 1.0,
+                    "{:?} stored {}: {} vs {}",
+                    format,
+                    stored,
+                    lut_grey,
+                    uniform_grey
+                );
+            }
+        }
+
+        // R32F samples are the values themselves, negative ones included
+        let lut_settings = MonoSettings {
+            float_range: (-1000.0, 1000.0),
+            window: None,
+        };
+        let (min, max) = lut_settings.float_range;
+        let float_table: Vec<u16> = (0..LUT_ENTRIES)
+            .map(|i| {
+                let value = min as f64 + i as f64 / u16::MAX as f64 * (max - min) as f64;
+                to_entry(window.apply(VoiFunction::Linear, value * 0.5 - 20.0))
+            })
+            .collect();
+        let float_uniform = UniformWindow {
+            bit_depth: BitDepth::default(),
+            ..uniform
+        };
+        for step in -1800..=1800 {
+            let sample = step as f32 * 0.5;
+            // The interpolation of FLOAT_SAMPLES between the LUT entries
+            let (scale, offset) = sample_mapping(PixelFormat::R32F, &lut_settings);
+            let position = (sample * scale + offset).clamp(0.0, 1.0) as f64 * u16::MAX as f64;
+            let index = position as usize;
+            let (low, high) = (
+                float_table[index] as f64,
+                float_table[(index + 1).min(u16::MAX as usize)] as f64,
+            );
+            let lut_grey = (low + (high - low) * position.fract()) / u16::MAX as f64 * 255.0;
+            let (scale, offset) = sample_mapping(PixelFormat::R32F, &uniform_settings);
+            let uniform_grey = float_uniform.apply_float(sample * scale + offset) as f64 * 255.0;
+            assert!(
+                (lut_grey.round() - uniform_grey.round()).abs() <= 1.0,
+                "R32F sample {}: {} vs {}",
+                sample,
+                lut_grey,
+                uniform_grey
+            );
+        }
+    }
+
+    #[test]
+    fn signed_r16_is_not_filtered() {
+        let signed = MonoSettings {
+            window: Some(UniformWindow {
+                bit_depth: BitDepth::signed(12).unwrap(),
+                ..UniformWindow::new(Window::new(0.0, 100.0))
+            }),
+            ..MonoSettings::default()
+        };
+        assert!(!filters_samples(PixelFormat::R16, &signed));
+        let variant = MonoVariant::new(PixelFormat::R16, None, &signed, Interpolation::Bicubic);
+        assert!(!variant.bicubic);
+        // SNORM samples are signed already
+        assert!(filters_samples(PixelFormat::R16S, &signed));
+        assert!(filters_samples(PixelFormat::R16, &MonoSettings::default()));
+    }
+
+    #[test]
+    fn every_mono_variant_is_precompiled() {
+        let all = MonoVariant::all();
+        assert_eq!(all.len(), 12);
+        let window = Some(UniformWindow::new(Window::new(0.5, 1.0)));
+        for &lut_format in &[None, Some(PixelFormat::R16), Some(PixelFormat::Rgba8)] {
+            for &window in &[None, window] {
+                for &interpolation in &[Interpolation::Bilinear, Interpolation::Bicubic] {
+                    let settings = MonoSettings {
+                        window,
+                        ..MonoSettings::default()
+                    };
+                    for &format in &[PixelFormat::R16, PixelFormat::R32F] {
+                        let variant =
+                            MonoVariant::new(format, lut_format, &settings, interpolation);
+                        assert!(all.contains(&variant));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn programs_match_the_channels() {
+        let mono = ImageProgram::for_format(PixelFormat::R16);
+        let argb = ImageProgram::for_format(PixelFormat::Rgba8);
+        assert!(mono.check_format(PixelFormat::R32F).is_ok());
+        assert!(mono.check_format(PixelFormat::Rgba8).is_err());
+        assert!(argb.check_format(PixelFormat::Rgb8).is_ok());
+        assert!(argb.check_format(PixelFormat::R8).is_err());
+        // Only mono images without a uniform window are drawn through the LUT
+        assert!(mono.reads_lut());
+        assert!(!argb.reads_lut());
+        let windowed = ImageProgram::Mono(MonoSettings {
+            window: Some(UniformWindow::new(Window::new(0.5, 1.0))),
+            ..MonoSettings::default()
+        });
+        assert!(!windowed.reads_lut());
     }
 }
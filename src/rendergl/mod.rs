@@ -1,4 +1,5 @@
 pub mod vertex;
 pub mod view_state;
 pub mod bindings;
-pub mod glrenderer;
\ No newline at end of file
+pub mod glrenderer;
+pub mod shader;
\ No newline at end of file
@@ -0,0 +1,216 @@
+use derive_more::Display;
+use std::fmt;
+
+/// GLSL source together with the file name used in diagnostics.
+#[derive(Debug, Clone, Copy)]
+pub struct ShaderSource<'a> {
+    pub name: &'a str,
+    pub text: &'a str,
+}
+
+impl<'a> ShaderSource<'a> {
+    pub const fn new(name: &'a str, text: &'a str) -> Self {
+        Self { name, text }
+    }
+
+    /// The source with `#define`s selecting a variant, inserted after the `#version`
+    /// line. Line numbers in driver logs still refer to the original source.
+    pub fn with_defines(&self, defines: &[&str]) -> String {
+        if defines.is_empty() {
+            return self.text.to_string();
+        }
+        let (version, body, first_line) = match self.text.strip_prefix("#version") {
+            Some(_) => {
+                let end = self.text.find('\n').map_or(self.text.len(), |i| i + 1);
+                (&self.text[..end], &self.text[end..], 2)
+            }
+            None => ("", self.text, 1),
+        };
+        let mut text = version.to_string();
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        for define in defines {
+            text.push_str(&format!("#define {}\n", define));
+        }
+        text.push_str(&format!("#line {}\n", first_line));
+        text.push_str(body);
+        text
+    }
+}
+
+#[derive(Debug, Display, Clone, Copy, PartialEq)]
+pub enum ShaderStage {
+    #[display(fmt = "vertex")]
+    Vertex,
+    #[display(fmt = "fragment")]
+    Fragment,
+    /// Linking the stages into a program.
+    #[display(fmt = "link")]
+    Link,
+}
+
+/// The stages that are compiled from source, see `ShaderStage` for errors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShaderType {
+    Vertex,
+    Fragment,
+}
+
+impl From<ShaderType> for ShaderStage {
+    fn from(shader_type: ShaderType) -> Self {
+        match shader_type {
+            ShaderType::Vertex => ShaderStage::Vertex,
+            ShaderType::Fragment => ShaderStage::Fragment,
+        }
+    }
+}
+
+/// One message from the driver's info log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShaderMessage {
+    /// Line in the source file, when the driver reported one.
+    pub line: Option<usize>,
+    /// The source text of that line.
+    pub source_line: Option<String>,
+    pub text: String,
+}
+
+/// A shader that failed to compile, or a program that failed to link.
+#[derive(Debug, Clone)]
+pub struct ShaderError {
+    pub stage: ShaderStage,
+    pub source_name: String,
+    pub messages: Vec<ShaderMessage>,
+}
+
+impl ShaderError {
+    /// Splits an info log into messages, mapping line numbers to `source` when given.
+    pub fn from_log(stage: ShaderStage, name: &str, log: &str, source: Option<&str>) -> Self {
+        let messages = log
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| match parse_location(line) {
+                Some((line, text)) => ShaderMessage {
+                    line: Some(line),
+                    source_line: source
+                        .and_then(|s| s.lines().nth(line.checked_sub(1)?))
+                        .map(|s| s.trim_end().to_string()),
+                    text,
+                },
+                None => ShaderMessage {
+                    line: None,
+                    source_line: None,
+                    text: line.to_string(),
+                },
+            })
+            .collect();
+        Self {
+            stage,
+            source_name: name.to_string(),
+            messages,
+        }
+    }
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.stage {
+            ShaderStage::Link => write!(f, "Failed to link {}", self.source_name)?,
+            stage => write!(f, "Failed to compile {} shader {}", stage, self.source_name)?,
+        }
+        for message in &self.messages {
+            match message.line {
+                Some(line) => write!(f, "\n{}:{}: {}", self.source_name, line, message.text)?,
+                None => write!(f, "\n{}: {}", self.source_name, message.text)?,
+            }
+            if let Some(source_line) = &message.source_line {
+                write!(f, "\n    | {}", source_line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Finds the line number in the log formats of the common drivers:
+// "0:12(5): error: ..." (Mesa), "0(12) : error C0000: ..." (NVIDIA) and
+// "ERROR: 0:12: ..." (AMD, Intel). Returns the line and the rest of the message.
+fn parse_location(line: &str) -> Option<(usize, String)> {
+    let start = line.find(|c: char| c.is_ascii_digit())?;
+    let severity = line[..start].trim().trim_end_matches(':');
+    if !severity.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    // Skip the source string index, always 0 as we pass a single string
+    let rest = line[start..].trim_start_matches(|c: char| c.is_ascii_digit());
+    let (rest, parenthesized) = match rest.strip_prefix('(') {
+        Some(rest) => (rest, true),
+        None => (rest.strip_prefix(':')?, false),
+    };
+    let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+    let number = rest[..digits].parse().ok()?;
+    let mut rest = &rest[digits..];
+    if parenthesized {
+        rest = rest.strip_prefix(')')?;
+    } else if let Some(column) = rest.strip_prefix('(') {
+        // Mesa adds the column
+        rest = &column[column.find(')')? + 1..];
+    }
+    let text = rest.trim_start_matches([' ', ':']).trim();
+    let text = if severity.is_empty() {
+        text.to_string()
+    } else {
+        format!("{}: {}", severity.to_lowercase(), text)
+    };
+    Some((number, text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_driver_log_formats() {
+        assert_eq!(
+            parse_location("0:12(5): error: `foo' undeclared"),
+            Some((12, "error: `foo' undeclared".to_string()))
+        );
+        assert_eq!(
+            parse_location("0(7) : error C1008: undefined variable \"foo\""),
+            Some((7, "error C1008: undefined variable \"foo\"".to_string()))
+        );
+        assert_eq!(
+            parse_location("ERROR: 0:3: 'foo' : undeclared identifier"),
+            Some((3, "error: 'foo' : undeclared identifier".to_string()))
+        );
+        assert_eq!(parse_location("error: linking failed in 2 stages"), None);
+    }
+
+    #[test]
+    fn maps_lines_to_source() {
+        let source = "#version 450\nvoid main() {\n    foo = 1;\n}\n";
+        let log = "0:3(5): error: `foo' undeclared\nlinker warning\n";
+        let error = ShaderError::from_log(ShaderStage::Fragment, "frag.glsl", log, Some(source));
+        assert_eq!(error.messages.len(), 2);
+        assert_eq!(error.messages[0].line, Some(3));
+        assert_eq!(
+            error.messages[0].source_line.as_deref(),
+            Some("    foo = 1;")
+        );
+        assert_eq!(error.messages[1].line, None);
+        assert!(error
+            .to_string()
+            .contains("frag.glsl:3: error: `foo' undeclared"));
+    }
+
+    #[test]
+    fn defines_follow_the_version() {
+        let source = ShaderSource::new("frag.glsl", "#version 450\nvoid main() {}\n");
+        assert_eq!(source.with_defines(&[]), source.text);
+        assert_eq!(
+            source.with_defines(&["PALETTE_LUT"]),
+            "#version 450\n#define PALETTE_LUT\n#line 2\nvoid main() {}\n"
+        );
+    }
+}
@@ -4,20 +4,155 @@
 in vec2 image_coord;
 out vec4 f_color;
 
-layout(binding=0) uniform sampler2D image_texture;
-layout(binding=1) uniform sampler2D lut_texture;
+// Every texture is an array, single images and the LUT have one layer.
+// The LUT is R16 grey, or RGBA with PALETTE_LUT defined. The image is unsigned or
+// signed integer, or float with FLOAT_SAMPLES defined. With UNIFORM_WINDOW defined a
+// linear window is applied without reading the LUT. With BICUBIC defined magnified images
+// are interpolated in the shader, the sampler only filters minified images.
+layout(binding=0) uniform sampler2DArray image_texture;
+layout(binding=1) uniform sampler2DArray lut_texture;
+// Set while drawing the parts of the image that have not been loaded yet
+layout(location=0) uniform bool placeholder;
+// The frame of a multi-frame image
+layout(location=1) uniform int layer;
+
+// From the sampled, normalized value to the stored value, or to [0, 1] over the LUT
+// with FLOAT_SAMPLES defined
+layout(location=2) uniform float sample_scale;
+layout(location=3) uniform float sample_offset;
+// The uniform window, (centre, width), (slope, intercept) and (min, max) of the stored
+// values, float samples are not clamped
+layout(location=4) uniform vec2 window;
+layout(location=5) uniform vec2 rescale;
+layout(location=6) uniform vec2 stored_range;
+layout(location=7) uniform bool invert;
+// Stored values are two's complement, signed images loaded as R16 are sign extended. They
+// are sampled without filtering, which would mix negative values with positive ones.
+layout(location=8) uniform bool sign_extend;
 
 const float LUT_MAX = float(1<<16) - 1.0;
 const uint LOG_LUT_IMG_SIZE = 8; // The LUT-image is assumed to be 256x256 (=65536 entries)
+const vec4 PLACEHOLDER_COLOR = vec4(0.25, 0.25, 0.25, 1.0);
+
+vec4 lut_entry(uint index) {
+    uint y = index >> LOG_LUT_IMG_SIZE;
+    uint x = index - (y << LOG_LUT_IMG_SIZE);
+    return texelFetch(lut_texture, ivec3(int(x), int(y), 0), 0);
+}
+
+// The linear VOI function of DICOM PS3.3 C.11.2.1.2, as lut::UniformWindow::apply_float.
+float apply_window(float value) {
+    float x = value * rescale.x + rescale.y;
+    float c = window.x;
+    float w = max(window.y, 1.0);
+    float y;
+    if (x <= c - 0.5 - (w - 1.0) / 2.0) {
+        y = 0.0;
+    } else if (x > c - 0.5 + (w - 1.0) / 2.0) {
+        y = 1.0;
+    } else {
+        y = (x - (c - 0.5)) / (w - 1.0) + 0.5;
+    }
+    return invert ? 1.0 - y : y;
+}
+
+#ifdef BICUBIC
+// Catmull-Rom weights of the four texels around a position t in [0, 1) between the
+// middle two
+vec4 cubic_weights(float t) {
+    float t2 = t * t;
+    float t3 = t2 * t;
+    return vec4(
+        -0.5 * t3 + t2 - 0.5 * t,
+        1.5 * t3 - 2.5 * t2 + 1.0,
+        -1.5 * t3 + 2.0 * t2 + 0.5 * t,
+        0.5 * t3 - 0.5 * t2);
+}
+
+// Interpolates the sampled values from the 4x4 closest texels. The result is clamped to
+// the range of the texels, overshoot past the largest stored value would wrap around
+// the LUT.
+float sample_bicubic() {
+    ivec2 size = textureSize(image_texture, 0).xy;
+    vec2 position = image_coord * vec2(size) - 0.5;
+    ivec2 base = ivec2(floor(position));
+    vec2 fraction = position - vec2(base);
+    vec4 weights_x = cubic_weights(fraction.x);
+    vec4 weights_y = cubic_weights(fraction.y);
+    float sum = 0.0;
+    float low = 1.0e30;
+    float high = -1.0e30;
+    for (int j = 0; j < 4; j++) {
+        float row = 0.0;
+        for (int i = 0; i < 4; i++) {
+            ivec2 texel = clamp(base + ivec2(i - 1, j - 1), ivec2(0), size - 1);
+            float value = texelFetch(image_texture, ivec3(texel, layer), 0).r;
+            row += weights_x[i] * value;
+            low = min(low, value);
+            high = max(high, value);
+        }
+        sum += weights_y[j] * row;
+    }
+    return clamp(sum, low, high);
+}
+#endif
+
+float sample_image() {
+    vec3 coord = vec3(image_coord, float(layer));
+#ifdef BICUBIC
+    // Implicit derivatives are undefined after the branch, they are taken up front and
+    // passed to textureGrad
+    vec2 dx = dFdx(image_coord);
+    vec2 dy = dFdy(image_coord);
+    vec2 texels_per_pixel = (abs(dx) + abs(dy)) * vec2(textureSize(image_texture, 0).xy);
+    if (max(texels_per_pixel.x, texels_per_pixel.y) < 1.0) {
+        return sample_bicubic();
+    }
+    return textureGrad(image_texture, coord, dx, dy).r;
+#else
+    return texture(image_texture, coord).r;
+#endif
+}
 
 void main() {
-    float val = texture(image_texture, image_coord).r;
-    uint stored_value = uint(val * LUT_MAX);
+    if (placeholder) {
+        f_color = PLACEHOLDER_COLOR;
+        return;
+    }
+    float val = sample_image();
+    float stored_value = val * sample_scale + sample_offset;
+
+#ifdef UNIFORM_WINDOW
+#ifndef FLOAT_SAMPLES
+    // The LUT path indexes with whole stored values
+    stored_value = round(stored_value);
+    // As lut::BitDepth::value reads 16 bit stored values
+    if (sign_extend && stored_value > 32767.0) {
+        stored_value -= 65536.0;
+    }
+    stored_value = clamp(stored_value, stored_range.x, stored_range.y);
+#endif
+    float windowed = apply_window(stored_value);
+    f_color = vec4(windowed, windowed, windowed, 1.0);
+    return;
+#endif
+
+#ifdef FLOAT_SAMPLES
+    // A continuous transfer function, interpolated between the LUT entries
+    float position = clamp(stored_value, 0.0, 1.0) * LUT_MAX;
+    uint index = uint(position);
+    vec4 entry = mix(lut_entry(index), lut_entry(min(index + 1u, uint(LUT_MAX))), fract(position));
+#else
+    // Negative values index the LUT with their two's complement, as the LUT is generated
+    vec4 entry = lut_entry(uint(int(round(stored_value))) & 0xFFFFu);
+#endif
 
-    uint y = stored_value >> LOG_LUT_IMG_SIZE;
-    uint x = stored_value - (y << LOG_LUT_IMG_SIZE);
-    ivec2 lut_coord = ivec2(int(x), int(y));
-    float norm_luminance = texelFetch(lut_texture, lut_coord, 0).r;
+#ifdef PALETTE_LUT
+    // Pseudo-colour, the LUT holds an RGBA colour per stored value
+    f_color = entry;
+#else
+    float norm_luminance = entry.r;
 
     f_color = vec4(norm_luminance, norm_luminance, norm_luminance, 1.0);
-}
\ No newline at end of file
+#endif
+}
@@ -0,0 +1,27 @@
+#version 450
+
+in vec2 image_coord;
+out vec4 f_color;
+
+// Colour images are stored as they are shown, there is no LUT
+layout(binding=0) uniform sampler2DArray image_texture;
+// Set while drawing the parts of the image that have not been loaded yet
+layout(location=0) uniform bool placeholder;
+// The frame of a multi-frame image
+layout(location=1) uniform int layer;
+// Per channel, contrast scales around mid grey and brightness is added after
+layout(location=2) uniform vec3 brightness;
+layout(location=3) uniform vec3 contrast;
+
+const vec4 PLACEHOLDER_COLOR = vec4(0.25, 0.25, 0.25, 1.0);
+
+void main() {
+    if (placeholder) {
+        f_color = PLACEHOLDER_COLOR;
+        return;
+    }
+    vec3 rgb = texture(image_texture, vec3(image_coord, float(layer))).rgb;
+    rgb = clamp((rgb - 0.5) * contrast + 0.5 + brightness, 0.0, 1.0);
+
+    f_color = vec4(rgb, 1.0);
+}
@@ -130,15 +130,36 @@ impl Quad {
         self.indexes.len() as u32
     }
 
+    // The texture may be larger than the image (power of two or aligned allocation) and does
+    // not have to be square, the texture coordinates then only cover the part holding the image.
     pub fn map_texture_coords(&mut self, img_dims: (f32, f32), tex_dims: (f32, f32)) {
-        let u = img_dims.0 / tex_dims.0;
-        let v = img_dims.1 / tex_dims.1;
+        assert!(img_dims.0 <= tex_dims.0 && img_dims.1 <= tex_dims.1);
+        self.map_tile_coords(img_dims, ((0.0, 0.0), img_dims), (0.0, 0.0), tex_dims);
+    }
+
+    // Maps the quad onto one tile of a larger image. The region (offset, size) is the part of
+    // the image covered by the quad and tex_origin is where the tile texture starts, both in
+    // image data texels. The transform still uses the full image so that all tiles line up.
+    pub fn map_tile_coords(
+        &mut self,
+        img_dims: (f32, f32),
+        region: ((f32, f32), (f32, f32)),
+        tex_origin: (f32, f32),
+        tex_dims: (f32, f32),
+    ) {
+        let ((x0, y0), (width, height)) = region;
         self.vertices
             .iter_mut()
             .zip(Self::VERTICES.iter())
             .for_each(|(v1, v2)| {
-                v1.position = [v2.position[0] * img_dims.0, v2.position[1] * img_dims.1];
-                v1.tex_coords = [v2.tex_coords[0] * u, v2.tex_coords[1] * v];
+                // Image data rows run opposite to the y-axis of the image space.
+                let data_x = x0 + v2.tex_coords[0] * width;
+                let data_y = y0 + v2.tex_coords[1] * height;
+                v1.position = [data_x, img_dims.1 - data_y];
+                v1.tex_coords = [
+                    (data_x - tex_origin.0) / tex_dims.0,
+                    (data_y - tex_origin.1) / tex_dims.1,
+                ];
             });
         self.image_size = img_dims;
         self.texture_size = tex_dims;
@@ -175,6 +196,56 @@ impl Quad {
     // }
 }
 
+/// Clips quad vertices (as from `Quad::get_vertex`) to a rectangle of texels, given as
+/// (offset, size) in a texture of `texture_size`. Returns `None` if they do not overlap.
+/// The quad maps texture coordinates along its sides, so the clipped quad shows exactly
+/// the texels of the rectangle at the same place on screen.
+pub fn clip_to_texels(
+    vertices: &[Vertex],
+    texture_size: (f32, f32),
+    rect: ((f32, f32), (f32, f32)),
+) -> Option<Vec<Vertex>> {
+    assert!(vertices.len() == Quad::VERTICES.len());
+    // Vertex 1 differs from vertex 0 in the second texture coordinate only, vertex 3 in
+    // the first, see `Quad::VERTICES`.
+    let (v0, v1, v3) = (&vertices[0], &vertices[1], &vertices[3]);
+    let du = v3.tex_coords[0] - v0.tex_coords[0];
+    let dv = v1.tex_coords[1] - v0.tex_coords[1];
+    if du == 0.0 || dv == 0.0 {
+        return None;
+    }
+    let ((x, y), (width, height)) = rect;
+    let lo = [x / texture_size.0, y / texture_size.1];
+    let hi = [(x + width) / texture_size.0, (y + height) / texture_size.1];
+    let overlaps = |a: f32, b: f32, axis: usize| a.min(b) < hi[axis] && a.max(b) > lo[axis];
+    if !overlaps(v0.tex_coords[0], v3.tex_coords[0], 0)
+        || !overlaps(v0.tex_coords[1], v1.tex_coords[1], 1)
+    {
+        return None;
+    }
+    let clipped = vertices
+        .iter()
+        .map(|v| {
+            let tex_coords = [
+                v.tex_coords[0].max(lo[0]).min(hi[0]),
+                v.tex_coords[1].max(lo[1]).min(hi[1]),
+            ];
+            let s = (tex_coords[0] - v0.tex_coords[0]) / du;
+            let t = (tex_coords[1] - v0.tex_coords[1]) / dv;
+            let along = |i: usize| {
+                v0.position[i]
+                    + s * (v3.position[i] - v0.position[i])
+                    + t * (v1.position[i] - v0.position[i])
+            };
+            Vertex {
+                position: [along(0), along(1)],
+                tex_coords,
+            }
+        })
+        .collect();
+    Some(clipped)
+}
+
 impl Default for Quad {
     fn default() -> Self {
         Quad::new()
@@ -259,4 +330,31 @@ mod tests {
         let v = q.get_vertex(&state);
         dbg!(v);
     }
+
+    #[test]
+    fn test_non_square_texture() {
+        let mut q = Quad::new();
+        // Exact allocation covers the whole texture
+        q.map_texture_coords((300_f32, 200_f32), (300_f32, 200_f32));
+        assert_eq!(q.vertices[0].tex_coords, [0.0, 1.0]);
+        assert_eq!(q.vertices[2].tex_coords, [1.0, 0.0]);
+        assert_eq!(q.vertices[2].position, [300.0, 200.0]);
+        // Aligned allocation only covers the image part of the texture
+        q.map_texture_coords((300_f32, 200_f32), (320_f32, 256_f32));
+        assert_eq!(q.vertices[0].tex_coords, [0.0, 200.0 / 256.0]);
+        assert_eq!(q.vertices[2].tex_coords, [300.0 / 320.0, 0.0]);
+    }
+
+    #[test]
+    fn test_clip_to_texels() {
+        let mut q = Quad::new();
+        q.map_texture_coords((64_f32, 32_f32), (64_f32, 32_f32));
+        // The first 8 rows of data are the top of the image
+        let v = clip_to_texels(&q.vertices, (64.0, 32.0), ((0.0, 0.0), (64.0, 8.0))).unwrap();
+        assert_eq!(v[0].tex_coords, [0.0, 0.25]);
+        assert_eq!(v[0].position, [0.0, 24.0]);
+        assert_eq!(v[2].tex_coords, [1.0, 0.0]);
+        assert_eq!(v[2].position, [64.0, 32.0]);
+        assert!(clip_to_texels(&q.vertices, (64.0, 32.0), ((64.0, 0.0), (8.0, 8.0))).is_none());
+    }
 }
@@ -6,6 +6,10 @@ pub struct ViewState {
     pub zoom: Zoom,
     pub pos: Position,
     pub frame: Option<u32>,
+    #[serde(default)]
+    pub min_filter: MinFilter,
+    #[serde(default)]
+    pub interpolation: Interpolation,
 }
 #[derive(Debug, Clone, Serialize, Deserialize, Copy)]
 #[serde(rename_all = "lowercase")]
@@ -14,6 +18,43 @@ pub enum Position {
     Aboslute((f32, f32)),
 }
 
+/// Filtering when the image is drawn smaller than its size. Both filter the stored
+/// values before the LUT is applied.
+#[derive(Debug, Clone, Serialize, Deserialize, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum MinFilter {
+    /// Bilinear filtering of the full resolution image, aliases when zoomed out far.
+    Linear,
+    /// Bilinear filtering between the two closest mip levels. Textures without a mip
+    /// chain are sampled as with `Linear`.
+    Trilinear,
+}
+
+impl Default for MinFilter {
+    fn default() -> Self {
+        MinFilter::Trilinear
+    }
+}
+
+/// Interpolation when the image is drawn larger than its size, of the stored values
+/// before the LUT is applied. Smaller images are filtered as set by `MinFilter`.
+#[derive(Debug, Clone, Serialize, Deserialize, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Interpolation {
+    /// Shows the pixels as squares, e.g. to inspect single pixels at high zoom.
+    Nearest,
+    Bilinear,
+    /// Smoother than `Bilinear` at moderate zoom, from the 4x4 closest pixels. Colour
+    /// images are drawn with `Bilinear`.
+    Bicubic,
+}
+
+impl Default for Interpolation {
+    fn default() -> Self {
+        Interpolation::Bilinear
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Copy)]
 #[serde(rename_all = "lowercase")]
 pub enum Zoom {
@@ -27,6 +68,8 @@ impl ViewState {
             zoom: Zoom::Fit(1.0),
             pos: Position::Relative((0.0, 0.0)),
             frame: None,
+            min_filter: MinFilter::default(),
+            interpolation: Interpolation::default(),
         }
     }
 
@@ -36,6 +79,8 @@ impl ViewState {
                 zoom: Zoom::Pixel(1.0),
                 pos: Position::Aboslute(position),
                 frame: None,
+                min_filter: MinFilter::default(),
+                interpolation: Interpolation::default(),
             });
         }
         None
@@ -0,0 +1,187 @@
+use crate::error::{Error, Result};
+use libloading::Library;
+use std::{
+    ffi::{c_void, CString},
+    os::raw,
+    ptr,
+};
+
+#[allow(clippy::all)]
+#[allow(non_camel_case_types)]
+pub mod egl {
+    pub type khronos_utime_nanoseconds_t = khronos_uint64_t;
+    pub type khronos_uint64_t = u64;
+    pub type khronos_ssize_t = std::os::raw::c_long;
+    pub type EGLint = i32;
+    pub type EGLNativeDisplayType = *const std::os::raw::c_void;
+    pub type EGLNativePixmapType = *const std::os::raw::c_void;
+    pub type EGLNativeWindowType = *const std::os::raw::c_void;
+    pub type NativeDisplayType = EGLNativeDisplayType;
+    pub type NativePixmapType = EGLNativePixmapType;
+    pub type NativeWindowType = EGLNativeWindowType;
+
+    include!("../bindings/egl_bindings.rs");
+}
+
+/// An OpenGL context on the Mesa surfaceless EGL platform. Unlike the glutin backends it
+/// needs no X11/Wayland connection and no event loop, only a render node.
+pub struct SurfacelessContext {
+    egl: egl::Egl,
+    display: egl::types::EGLDisplay,
+    context: egl::types::EGLContext,
+    // Keeps the function pointers in `egl` valid, dropped last.
+    _library: Library,
+}
+
+// The handles are only used from the uploader thread, and moved there once.
+unsafe impl Send for SurfacelessContext {}
+
+impl SurfacelessContext {
+    /// Creates a 4.5 core context, it is not current on any thread.
+    pub fn new() -> Result<Self> {
+        let library = Library::new("libEGL.so.1")
+            .map_err(|e| Error::Context(format!("Failed to load libEGL: {}", e)))?;
+        let egl = egl::Egl::load_with(|name| {
+            let name = CString::new(name).unwrap();
+            unsafe {
+                library
+                    .get::<*const c_void>(name.as_bytes_with_nul())
+                    .map(|symbol| *symbol)
+                    .unwrap_or(ptr::null())
+            }
+        });
+        if !egl.GetPlatformDisplay.is_loaded() {
+            return Err(Error::Context("libEGL does not support EGL 1.5".into()));
+        }
+        unsafe {
+            let display = egl.GetPlatformDisplay(
+                egl::PLATFORM_SURFACELESS_MESA,
+                egl::DEFAULT_DISPLAY as *mut _,
+                ptr::null(),
+            );
+            if display == egl::NO_DISPLAY {
+                return Err(Error::Context(
+                    "EGL_MESA_platform_surfaceless is not supported".into(),
+                ));
+            }
+            let (mut major, mut minor) = (0, 0);
+            if egl.Initialize(display, &mut major, &mut minor) == egl::FALSE {
+                return Err(Self::error(&egl, "Failed to initialize the EGL display"));
+            }
+            let context = match Self::create_context(&egl, display) {
+                Ok(context) => context,
+                Err(e) => {
+                    egl.Terminate(display);
+                    return Err(e);
+                }
+            };
+            Ok(Self {
+                egl,
+                display,
+                context,
+                _library: library,
+            })
+        }
+    }
+
+    unsafe fn create_context(
+        egl: &egl::Egl,
+        display: egl::types::EGLDisplay,
+    ) -> Result<egl::types::EGLContext> {
+        if egl.BindAPI(egl::OPENGL_API) == egl::FALSE {
+            return Err(Self::error(egl, "Desktop OpenGL is not supported"));
+        }
+        // No surface is ever created, any config that can render OpenGL will do.
+        let config_attributes = [
+            egl::RENDERABLE_TYPE as i32,
+            egl::OPENGL_BIT as i32,
+            egl::SURFACE_TYPE as i32,
+            0,
+            egl::NONE as i32,
+        ];
+        let mut config = ptr::null();
+        let mut config_count = 0;
+        if egl.ChooseConfig(
+            display,
+            config_attributes.as_ptr(),
+            &mut config,
+            1,
+            &mut config_count,
+        ) == egl::FALSE
+            || config_count == 0
+        {
+            return Err(Self::error(egl, "No EGL config for OpenGL"));
+        }
+        let context_attributes = [
+            egl::CONTEXT_MAJOR_VERSION as i32,
+            4,
+            egl::CONTEXT_MINOR_VERSION as i32,
+            5,
+            egl::CONTEXT_OPENGL_PROFILE_MASK as i32,
+            egl::CONTEXT_OPENGL_CORE_PROFILE_BIT as i32,
+            egl::NONE as i32,
+        ];
+        let context = egl.CreateContext(
+            display,
+            config,
+            egl::NO_CONTEXT,
+            context_attributes.as_ptr(),
+        );
+        if context == egl::NO_CONTEXT {
+            return Err(Self::error(egl, "Failed to create an OpenGL 4.5 context"));
+        }
+        Ok(context)
+    }
+
+    fn error(egl: &egl::Egl, message: &str) -> Error {
+        Error::Context(format!("{} (EGL error 0x{:x})", message, unsafe {
+            egl.GetError()
+        }))
+    }
+
+    pub unsafe fn make_current(&self) -> Result<()> {
+        match self
+            .egl
+            .MakeCurrent(self.display, egl::NO_SURFACE, egl::NO_SURFACE, self.context)
+        {
+            egl::FALSE => Err(Self::error(&self.egl, "Failed to make context current")),
+            _ => Ok(()),
+        }
+    }
+
+    pub unsafe fn make_not_current(&self) -> Result<()> {
+        match self.egl.MakeCurrent(
+            self.display,
+            egl::NO_SURFACE,
+            egl::NO_SURFACE,
+            egl::NO_CONTEXT,
+        ) {
+            egl::FALSE => Err(Self::error(&self.egl, "Failed to uncurrent the context")),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn get_proc_address(&self, name: &str) -> *const raw::c_void {
+        let name = CString::new(name).unwrap();
+        unsafe { self.egl.GetProcAddress(name.as_ptr()) as *const _ }
+    }
+
+    pub fn egl_display(&self) -> egl::types::EGLDisplay {
+        self.display
+    }
+
+    pub fn egl_context(&self) -> egl::types::EGLContext {
+        self.context
+    }
+}
+
+impl Drop for SurfacelessContext {
+    fn drop(&mut self) {
+        unsafe {
+            // Deletion is deferred while the context is still current on this thread.
+            self.make_not_current().ok();
+            self.egl.DestroyContext(self.display, self.context);
+            self.egl.Terminate(self.display);
+        }
+    }
+}
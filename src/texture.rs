@@ -1,178 +1,945 @@
-use crate::{bidir::BidirChannel, rendergl::bindings::gl};
-use core::panic;
+#[cfg(target_os = "linux")]
+use crate::surfaceless::SurfacelessContext;
+use crate::{
+    error::{Error, Result},
+    pixel_format::{PixelFormat, Sample, SampleData},
+    region::{Region, ValidRegion},
+    rendergl::bindings::gl,
+    texture_handle::{GlTexture, TextureHandle, TextureSlot},
+    texture_pool::{AllocationPolicy, PoolKey, TexturePool},
+    tiles::{self, TileLayout},
+    upload_token::UploadToken,
+};
+use derive_more::Display;
+#[cfg(target_os = "linux")]
+use glutin::platform::unix::{
+    EventLoopExtUnix, EventLoopWindowTargetExtUnix, HeadlessContextExt, RawHandle,
+};
+#[cfg(target_os = "windows")]
+use glutin::platform::windows::{EventLoopExtWindows, RawHandle};
 use glutin::{
-    dpi::PhysicalSize,
-    event_loop::EventLoop,
-    platform::{
-        windows::{EventLoopExtWindows, RawHandle},
-        ContextTraitExt,
-    },
-    Context, PossiblyCurrent,
+    dpi::PhysicalSize, event_loop::EventLoop, platform::ContextTraitExt, Context,
+    ContextCurrentState, NotCurrent, PossiblyCurrent,
 };
 use gst_gl::prelude::*;
 use gstreamer_gl as gst_gl;
-use std::{ffi::CStr, mem, ptr, thread};
+use std::{
+    any::Any,
+    collections::HashMap,
+    ffi::{c_void, CStr},
+    mem, ptr,
+    str::FromStr,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// The kind of headless context the uploader thread renders with.
+#[derive(Debug, Display, Clone, Copy, PartialEq)]
+pub enum HeadlessBackend {
+    /// Pbuffer context on the default display (the platform default).
+    #[display(fmt = "pbuffer")]
+    Pbuffer,
+    /// EGL context on the Mesa surfaceless platform, needs neither a display server nor
+    /// an event loop.
+    #[display(fmt = "surfaceless")]
+    Surfaceless,
+    /// Mesa software rasterizer, needs neither a display nor a GPU.
+    /// GStreamer can not wrap OSMesa, so there is no shared context in this mode.
+    #[display(fmt = "osmesa")]
+    OsMesa,
+}
+
+impl HeadlessBackend {
+    /// Environment variable used to select the backend, e.g. `GLTEST_GL_BACKEND=osmesa`.
+    pub const ENV_VAR: &'static str = "GLTEST_GL_BACKEND";
+
+    /// The default backend if the variable is not set, fails on a name that is not known.
+    pub fn from_env() -> Result<Self> {
+        match std::env::var(Self::ENV_VAR) {
+            Ok(name) => name
+                .parse()
+                .map_err(|e| Error::UnknownBackend(format!("{}: {}", Self::ENV_VAR, e))),
+            Err(_) => Ok(HeadlessBackend::default()),
+        }
+    }
+}
+
+impl Default for HeadlessBackend {
+    fn default() -> Self {
+        HeadlessBackend::Pbuffer
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UploaderConfig {
+    pub backend: HeadlessBackend,
+    /// Upper limit in bytes for all textures owned by the uploader, pooled ones included.
+    pub texture_budget: usize,
+    pub allocation: AllocationPolicy,
+    /// Number of pixel buffer objects uploads are streamed through, 0 uploads directly
+    /// from client memory.
+    pub pixel_buffers: usize,
+    /// Generate the mip chain of image textures, for smooth minification. A frame's chain
+    /// is built once the loads since the last build cover the image, and by `flush` for
+    /// frames loaded only in part. The levels average the stored values, the LUT is
+    /// applied after sampling. These
+    /// textures have the exact image size whatever the allocation policy, and the budget
+    /// counts their whole chain.
+    pub mipmaps: bool,
+}
+
+impl Default for UploaderConfig {
+    fn default() -> Self {
+        Self {
+            backend: HeadlessBackend::default(),
+            texture_budget: 512 * 1024 * 1024,
+            allocation: AllocationPolicy::default(),
+            pixel_buffers: 3,
+            mipmaps: false,
+        }
+    }
+}
+
+impl FromStr for HeadlessBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pbuffer" => Ok(HeadlessBackend::Pbuffer),
+            "surfaceless" => Ok(HeadlessBackend::Surfaceless),
+            "osmesa" => Ok(HeadlessBackend::OsMesa),
+            other => Err(format!(
+                "Unknown headless backend '{}', expected one of: pbuffer, surfaceless, osmesa",
+                other
+            )),
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum TextureType {
     Mono,
+    Color,
     Lut,
+    /// An RGBA colour per stored value, for pseudo-colour display of mono images.
+    PaletteLut,
+}
+
+impl TextureType {
+    pub fn is_lut(&self) -> bool {
+        matches!(self, TextureType::Lut | TextureType::PaletteLut)
+    }
 }
 #[derive(Debug, Clone)]
 pub struct TextureDescription {
     pub handle: TextureHandle,
     pub kind: TextureType,
+    /// The size of the image the texture was acquired for, the texture may be larger.
+    pub size: (usize, usize),
+    // One per frame, shared by the clones so every holder sees the same loads.
+    loaded: Arc<Mutex<Vec<LoadedRegion>>>,
+}
+
+// Loads are only counted as valid once the GPU has written them.
+#[derive(Debug, Default)]
+struct LoadedRegion {
+    valid: ValidRegion,
+    pending: Vec<(Region, UploadToken)>,
+}
+
+impl TextureDescription {
+    fn new(handle: TextureHandle, kind: TextureType, size: (usize, usize)) -> Self {
+        let loaded = (0..handle.layers)
+            .map(|_| LoadedRegion::default())
+            .collect();
+        Self {
+            handle,
+            kind,
+            size,
+            loaded: Arc::new(Mutex::new(loaded)),
+        }
+    }
+
+    /// Number of frames of a multi-frame image, 1 otherwise.
+    pub fn frames(&self) -> usize {
+        self.handle.layers
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.handle.format
+    }
+
+    /// The part of the frame whose loads have completed, failed loads are left out.
+    /// `None` if the image has no such frame.
+    pub fn valid_region(&self, frame: usize) -> Option<ValidRegion> {
+        self.synced_region(frame, &[])
+    }
+
+    /// As `valid_region`, also counting the loads of `synced`, uploads the caller's
+    /// context has already waited for with `glWaitSync`. Their completion is only seen
+    /// once the uploader thread polls the fence.
+    pub fn synced_region(&self, frame: usize, synced: &[UploadToken]) -> Option<ValidRegion> {
+        let mut loaded = self.loaded.lock().unwrap();
+        let LoadedRegion { valid, pending } = loaded.get_mut(frame)?;
+        pending.retain(|(region, token)| {
+            if !token.is_complete() {
+                return true;
+            }
+            if token.wait().is_ok() {
+                valid.add(*region);
+            }
+            false
+        });
+        let mut region = valid.clone();
+        for (pending_region, token) in pending.iter() {
+            if synced.iter().any(|synced| synced.same_upload(token)) {
+                region.add(*pending_region);
+            }
+        }
+        Some(region)
+    }
+
+    /// True once every frame has been loaded completely.
+    pub fn is_complete(&self) -> bool {
+        let image = Region::new((0, 0), self.size);
+        (0..self.frames()).all(|frame| {
+            self.valid_region(frame)
+                .map_or(false, |valid| valid.covers(&image))
+        })
+    }
+
+    fn track_load(&self, frame: usize, region: Region, token: &UploadToken) {
+        let mut loaded = self.loaded.lock().unwrap();
+        loaded[frame].pending.push((region, token.clone()));
+    }
+}
+#[derive(Debug, Clone)]
+pub struct ImageTile {
+    pub layout: TileLayout,
+    pub texture: TextureDescription,
+}
+/// An image stored in one or more textures, see `tiles::tile_layout`.
+#[derive(Debug, Clone)]
+pub struct TiledImageDescription {
+    pub size: (usize, usize),
+    pub tiles: Vec<ImageTile>,
 }
 #[derive(Debug)]
 struct DataDescription {
+    layer: usize,
+    offset: (usize, usize),
     size: (usize, usize),
-    data: Vec<u16>,
+    data: SampleData,
 }
 #[derive(Debug)]
 struct LoadDescription {
-    texture: TextureHandle,
+    texture: GlTexture,
+    // Edges are replicated into the padding when a load reaches the end of the image.
+    image_size: (usize, usize),
     data: DataDescription,
+    token: UploadToken,
 }
 #[derive(Debug)]
 pub enum UploaderMessage {
     GetContext,
     SetContextCurrent,
-    Context(gst_gl::GLContext),
+    Context(Option<gst_gl::GLContext>),
     LoadData(LoadDescription),
-    Texture(Option<TextureHandle>),
-    AcquireTexture((usize, usize)),
-    ReleaseTexture(TextureHandle),
+    Texture(Option<GlTexture>),
+    /// Format, size, number of layers and whether it has a mip chain.
+    AcquireTexture(PoolKey),
+    ReleaseTexture(GlTexture),
+    GetMaxTextureSize,
+    MaxTextureSize(usize),
+    GetMaxTextureLayers,
+    MaxTextureLayers(usize),
+    /// Reads back the whole texture as the GPU holds it.
+    ReadTexture(GlTexture),
+    TextureData(SampleData),
     Flush,
+    /// Releases every texture and destroys the context, then the thread exits.
+    Shutdown,
     Fail(String),
 }
-// This is not thread safe!!!!!!!!!!! (needs a Mutex if multiple threads should access it)
+// A message for the uploader thread, with the channel to reply on if it is a request.
+#[derive(Debug)]
+struct Envelope {
+    message: UploaderMessage,
+    reply: Option<Sender<UploaderMessage>>,
+}
+
+impl Envelope {
+    fn respond(self, message: UploaderMessage) {
+        if let Some(reply) = self.reply {
+            // The requester may have given up waiting, that is fine.
+            let _ = reply.send(message);
+        }
+    }
+
+    // Requests get a `Fail` reply, uploads fail their token.
+    fn fail(self, reason: String) {
+        match self.message {
+            UploaderMessage::LoadData(desc) => {
+                desc.token.fail(reason);
+            }
+            _ => self.respond(UploaderMessage::Fail(reason)),
+        }
+    }
+}
+
+// The headless context, its event loop (if the backend has one) and the context shared
+// with GStreamer (if the backend can be wrapped).
+type HeadlessContext = (
+    Option<EventLoop<()>>,
+    MainContext<PossiblyCurrent>,
+    Option<gst_gl::GLContext>,
+);
+
+// The context the uploader renders with, built by glutin or directly on EGL.
+enum MainContext<T: ContextCurrentState> {
+    Glutin(Context<T>),
+    #[cfg(target_os = "linux")]
+    Surfaceless(Box<SurfacelessContext>),
+}
+
+impl MainContext<NotCurrent> {
+    unsafe fn make_current(self) -> Result<MainContext<PossiblyCurrent>> {
+        match self {
+            MainContext::Glutin(context) => context
+                .make_current()
+                .map(MainContext::Glutin)
+                .map_err(|(_, e)| Error::Context(format!("Failed to make context current: {}", e))),
+            #[cfg(target_os = "linux")]
+            MainContext::Surfaceless(context) => {
+                context.make_current()?;
+                Ok(MainContext::Surfaceless(context))
+            }
+        }
+    }
+}
+
+impl MainContext<PossiblyCurrent> {
+    unsafe fn make_not_current(self) -> Result<MainContext<NotCurrent>> {
+        match self {
+            MainContext::Glutin(context) => context
+                .make_not_current()
+                .map(MainContext::Glutin)
+                .map_err(|(_, e)| {
+                    Error::Context(format!("Failed to uncurrent the main context: {}", e))
+                }),
+            #[cfg(target_os = "linux")]
+            MainContext::Surfaceless(context) => {
+                context.make_not_current()?;
+                Ok(MainContext::Surfaceless(context))
+            }
+        }
+    }
+
+    fn get_proc_address(&self, name: &str) -> *const c_void {
+        match self {
+            MainContext::Glutin(context) => context.get_proc_address(name),
+            #[cfg(target_os = "linux")]
+            MainContext::Surfaceless(context) => context.get_proc_address(name),
+        }
+    }
+}
+
+/// Handle to the uploader thread. Clones talk to the same thread, and every request has
+/// its own reply channel, so any number of threads can acquire and load textures at once.
+/// The thread is shut down and joined when the last clone is dropped, or by `shutdown`.
+#[derive(Clone)]
 pub struct ThreadUploader {
-    channel: BidirChannel<UploaderMessage>,
+    thread: Arc<UploaderThread>,
+    allocation: AllocationPolicy,
+    mipmaps: bool,
+}
+
+struct UploaderThread {
+    // A SyncSender, unlike Sender, is Sync. Its bound also keeps loading threads from
+    // queueing up image data faster than it can be uploaded.
+    sender: SyncSender<Envelope>,
+    // Taken when the thread is joined
+    handle: Mutex<Option<JoinHandle<()>>>,
+    // Set if the thread died from a panic
+    panic: Mutex<Option<String>>,
+}
+
+impl UploaderThread {
+    fn shutdown(&self) -> Result<()> {
+        // Requests queued before this one are still served. If the thread is already
+        // gone the send fails, and the join tells why.
+        let _ = self.sender.send(Envelope {
+            message: UploaderMessage::Shutdown,
+            reply: None,
+        });
+        self.join()
+    }
+
+    // Waits for the thread to exit, an error if it panicked.
+    fn join(&self) -> Result<()> {
+        if let Some(handle) = self.handle.lock().unwrap().take() {
+            if let Err(panic) = handle.join() {
+                *self.panic.lock().unwrap() = Some(panic_message(&*panic));
+            }
+        }
+        match &*self.panic.lock().unwrap() {
+            Some(message) => Err(Error::UploaderPanicked(message.clone())),
+            None => Ok(()),
+        }
+    }
+
+    // The error for a message the thread did not take, or a request it did not answer.
+    fn gone(&self) -> Error {
+        match self.join() {
+            Ok(()) => Error::UploaderGone,
+            Err(e) => e,
+        }
+    }
+}
+
+impl Drop for UploaderThread {
+    fn drop(&mut self) {
+        if self.handle.lock().unwrap().is_none() {
+            // Already shut down explicitly
+            return;
+        }
+        if let Err(e) = self.shutdown() {
+            println!("{}", e);
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match panic.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "Unknown panic".into(),
+        },
+    }
 }
 
 impl ThreadUploader {
     const LUT_TEXTURE_WIDTH: usize = 256;
     const LUT_TEXTURE_HEIGHT: usize = 256;
 
-    pub fn new() -> Self {
-        let (me, other) = BidirChannel::new_pair();
-        let _ = thread::spawn(move || Self::thread_func(other));
-        Self { channel: me }
+    const HEADLESS_SIZE: PhysicalSize<u32> = PhysicalSize::new(100, 100);
+    const FENCE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+    const MAX_FENCE_POLL_INTERVAL: Duration = Duration::from_millis(32);
+    const QUEUE_LENGTH: usize = 64;
+
+    /// Creates an uploader using the backend selected by `HeadlessBackend::ENV_VAR`.
+    pub fn new() -> Result<Self> {
+        Ok(Self::with_backend(HeadlessBackend::from_env()?))
     }
-    pub fn get_shared_context(&self) -> gst_gl::GLContext {
-        self.channel
-            .send(UploaderMessage::GetContext)
-            .expect("Failed to send GetContext");
-        let message = self.channel.recv().expect("Failed to recv context");
-        match message {
-            UploaderMessage::Context(ctx) => ctx,
-            m => panic!("Failed to recieve context got: {:?}", m),
+
+    pub fn with_backend(backend: HeadlessBackend) -> Self {
+        // The default configuration needs no validation
+        Self::spawn(UploaderConfig {
+            backend,
+            ..Default::default()
+        })
+    }
+
+    /// Fails without starting the thread if the configuration is invalid.
+    pub fn with_config(config: UploaderConfig) -> Result<Self> {
+        config.allocation.validate()?;
+        Ok(Self::spawn(config))
+    }
+
+    fn spawn(config: UploaderConfig) -> Self {
+        let (allocation, mipmaps) = (config.allocation, config.mipmaps);
+        Self::spawn_thread(allocation, mipmaps, move |receiver| {
+            Self::thread_func(receiver, config)
+        })
+    }
+
+    // Runs `thread_func` as the uploader thread, it serves the messages from the receiver.
+    fn spawn_thread<F>(allocation: AllocationPolicy, mipmaps: bool, thread_func: F) -> Self
+    where
+        F: FnOnce(Receiver<Envelope>) + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(Self::QUEUE_LENGTH);
+        let handle = thread::spawn(move || thread_func(receiver));
+        Self {
+            thread: Arc::new(UploaderThread {
+                sender,
+                handle: Mutex::new(Some(handle)),
+                panic: Mutex::new(None),
+            }),
+            allocation,
+            mipmaps,
+        }
+    }
+    fn send(&self, message: UploaderMessage) -> Result<()> {
+        self.thread
+            .sender
+            .send(Envelope {
+                message,
+                reply: None,
+            })
+            .map_err(|_| self.thread.gone())
+    }
+    // Sends a message and waits for the reply, a `Fail` reply is turned into an error.
+    fn request(&self, message: UploaderMessage) -> Result<UploaderMessage> {
+        let (reply, receiver) = mpsc::channel();
+        self.thread
+            .sender
+            .send(Envelope {
+                message,
+                reply: Some(reply),
+            })
+            .map_err(|_| self.thread.gone())?;
+        match receiver.recv() {
+            Ok(UploaderMessage::Fail(reason)) => Err(Error::Uploader(reason)),
+            Ok(reply) => Ok(reply),
+            Err(_) => Err(self.thread.gone()),
+        }
+    }
+
+    // Gives the client ownership of a texture, it is released when the last clone drops.
+    fn wrap_texture(&self, texture: GlTexture) -> TextureHandle {
+        let sender = self.thread.sender.clone();
+        TextureHandle::new(texture, move |texture| {
+            // If the uploader is gone its textures have been deleted already
+            let _ = sender.send(Envelope {
+                message: UploaderMessage::ReleaseTexture(texture),
+                reply: None,
+            });
+        })
+    }
+
+    /// Releases every texture, destroys the headless context and joins the thread.
+    /// Requests already queued are served first, any later request from a clone fails.
+    /// Returns an error if the thread had died from a panic.
+    pub fn shutdown(&self) -> Result<()> {
+        self.thread.shutdown()
+    }
+
+    /// Returns `None` when the headless backend can not be shared with GStreamer (OSMesa).
+    pub fn get_shared_context(&self) -> Result<Option<gst_gl::GLContext>> {
+        match self.request(UploaderMessage::GetContext)? {
+            UploaderMessage::Context(ctx) => Ok(ctx),
+            m => Err(Error::UnexpectedReply(format!("{:?}", m))),
         }
     }
-    pub fn set_current(&self) {
-        self.channel
-            .send(UploaderMessage::SetContextCurrent)
-            .expect("Failed to send currenting message");
+    pub fn set_current(&self) -> Result<()> {
+        self.send(UploaderMessage::SetContextCurrent)
     }
 
-    pub fn acquire_image_handle(&self, size: (usize, usize)) -> Option<TextureDescription> {
-        let (width, height) = Self::alloc_size(size.0, size.1);
-        self.channel
-            .send(UploaderMessage::AcquireTexture((width, height)))
-            .expect("Failed to send aquire message");
-        let message = self.channel.recv().expect("Failed to recv message");
+    /// Returns `None` when the texture does not fit in the texture budget.
+    pub fn acquire_image_handle(
+        &self,
+        format: PixelFormat,
+        size: (usize, usize),
+    ) -> Result<Option<TextureDescription>> {
+        self.acquire_frames_handle(format, size, 1)
+    }
+
+    /// Acquires one texture holding all frames of a multi-frame image, see `load_frame`.
+    /// The renderer selects the frame to draw from `ViewState::frame`.
+    /// Returns `None` when the texture does not fit in the texture budget.
+    pub fn acquire_frames_handle(
+        &self,
+        format: PixelFormat,
+        size: (usize, usize),
+        frames: usize,
+    ) -> Result<Option<TextureDescription>> {
+        if frames == 0 {
+            return Err(Error::InvalidUpload(
+                "An image needs at least one frame".into(),
+            ));
+        }
+        // A single frame always fits, this saves a round trip for plain images
+        if frames > 1 {
+            let max_frames = self.max_texture_layers()?;
+            if frames > max_frames {
+                return Err(Error::InvalidUpload(format!(
+                    "{} frames, the GL supports at most {}",
+                    frames, max_frames
+                )));
+            }
+        }
+        // The mip chain would average padding into the edges of the image
+        let texture_size = match self.mipmaps {
+            true => size,
+            false => self.allocation.alloc_size(size.0, size.1),
+        };
+        self.acquire_frames(format, texture_size, size, frames)
+    }
+    // Acquires a texture of exactly `texture_size`, the image covers `size` of it.
+    fn acquire_frames(
+        &self,
+        format: PixelFormat,
+        texture_size: (usize, usize),
+        size: (usize, usize),
+        frames: usize,
+    ) -> Result<Option<TextureDescription>> {
+        let key = PoolKey::layered(format, texture_size.0, texture_size.1, frames)
+            .with_mipmaps(self.mipmaps);
+        let message = self.request(UploaderMessage::AcquireTexture(key))?;
+        let kind = match format.channels() {
+            1 => TextureType::Mono,
+            _ => TextureType::Color,
+        };
         match message {
-            UploaderMessage::Texture(handle) => Some(TextureDescription {
-                handle: handle?,
-                kind: TextureType::Mono,
-            }),
-            m => panic!("Unexpected message type: {:?}", m),
+            UploaderMessage::Texture(texture) => Ok(texture
+                .map(|texture| TextureDescription::new(self.wrap_texture(texture), kind, size))),
+            m => Err(Error::UnexpectedReply(format!("{:?}", m))),
         }
     }
-    pub fn acquire_lut_handle(&self) -> Option<TextureDescription> {
+    pub fn acquire_lut_handle(&self) -> Result<Option<TextureDescription>> {
+        self.acquire_lut(PixelFormat::R16, TextureType::Lut)
+    }
+    /// A LUT for `load_palette_lut`, drawn in colour instead of grey.
+    pub fn acquire_palette_lut_handle(&self) -> Result<Option<TextureDescription>> {
+        self.acquire_lut(PixelFormat::Rgba8, TextureType::PaletteLut)
+    }
+    fn acquire_lut(
+        &self,
+        format: PixelFormat,
+        kind: TextureType,
+    ) -> Result<Option<TextureDescription>> {
         let (width, height) = (Self::LUT_TEXTURE_WIDTH, Self::LUT_TEXTURE_HEIGHT);
-        self.channel
-            .send(UploaderMessage::AcquireTexture((width, height)))
-            .expect("Failed to send lut aquire message");
-        let message = self.channel.recv().expect("Failed to recv message");
+        // The LUT is read with texelFetch, a mip chain would only waste memory
+        let key = PoolKey::new(format, width, height);
+        let message = self.request(UploaderMessage::AcquireTexture(key))?;
         match message {
-            UploaderMessage::Texture(handle) => Some(TextureDescription {
-                handle: handle?,
-                kind: TextureType::Lut,
-            }),
-            m => panic!("Unexpected message type: {:?}", m),
+            UploaderMessage::Texture(texture) => Ok(texture.map(|texture| {
+                TextureDescription::new(self.wrap_texture(texture), kind, (width, height))
+            })),
+            m => Err(Error::UnexpectedReply(format!("{:?}", m))),
         }
     }
-    fn load_texture(&self, texture: &TextureHandle, size: (usize, usize), data: Vec<u16>) {
-        assert!(size.0 <= texture.width && size.1 <= texture.height);
-        assert!(size.0 * size.1 == data.len());
-        self.channel
-            .send(UploaderMessage::LoadData(LoadDescription {
-                texture: texture.clone(),
-                data: DataDescription { size, data },
-            }))
-            .expect("Failed to send upload message");
+    fn load_texture<T: Sample>(
+        &self,
+        description: &TextureDescription,
+        frame: usize,
+        offset: (usize, usize),
+        size: (usize, usize),
+        data: Vec<T>,
+    ) -> Result<UploadToken> {
+        let texture = &description.handle;
+        if !texture.is_current() {
+            return Err(Error::StaleTexture(texture.id));
+        }
+        if frame >= texture.layers {
+            return Err(Error::InvalidUpload(format!(
+                "Frame {} of an image with {} frames",
+                frame, texture.layers
+            )));
+        }
+        let end = Region::new(offset, size).end();
+        if end.0 > texture.width || end.1 > texture.height {
+            return Err(Error::InvalidUpload(format!(
+                "{}x{} at ({}, {}) does not fit in a {}x{} texture",
+                size.0, size.1, offset.0, offset.1, texture.width, texture.height
+            )));
+        }
+        // The samples must match the storage format of the texture
+        if T::SAMPLE_TYPE != texture.format.sample_type() {
+            return Err(Error::InvalidUpload(format!(
+                "{:?} samples for a {:?} texture",
+                T::SAMPLE_TYPE,
+                texture.format
+            )));
+        }
+        if texture.format.sample_count(size) != data.len() {
+            return Err(Error::InvalidUpload(format!(
+                "Expected {} samples, got {}",
+                texture.format.sample_count(size),
+                data.len()
+            )));
+        }
+        let token = UploadToken::new();
+        self.send(UploaderMessage::LoadData(LoadDescription {
+            texture: texture.texture().clone(),
+            image_size: description.size,
+            data: DataDescription {
+                layer: frame,
+                offset,
+                size,
+                data: T::into_data(data),
+            },
+            token: token.clone(),
+        }))?;
+        description.track_load(frame, Region::new(offset, size), &token);
+        Ok(token)
     }
-    pub fn load_image(&self, texture: &TextureDescription, size: (usize, usize), data: Vec<u16>) {
-        assert!(texture.kind == TextureType::Mono);
-        self.load_texture(&texture.handle, size, data);
+    /// The returned token tells when the texture has actually been written by the GPU.
+    /// Multi-frame images get the data in their first frame, see `load_frame`.
+    pub fn load_image<T: Sample>(
+        &self,
+        texture: &TextureDescription,
+        size: (usize, usize),
+        data: Vec<T>,
+    ) -> Result<UploadToken> {
+        self.load_image_region(texture, (0, 0), size, data)
+    }
+
+    /// Loads part of the image, e.g. lines or tiles as they arrive. The region is added to
+    /// the valid region of the texture once the GPU has written it.
+    pub fn load_image_region<T: Sample>(
+        &self,
+        texture: &TextureDescription,
+        offset: (usize, usize),
+        size: (usize, usize),
+        data: Vec<T>,
+    ) -> Result<UploadToken> {
+        self.load_frame_region(texture, 0, offset, size, data)
     }
 
-    pub fn load_lut(&self, texture: &TextureDescription, data: Vec<u16>) {
-        assert!(texture.kind == TextureType::Lut);
+    /// Loads one frame of a multi-frame image.
+    pub fn load_frame<T: Sample>(
+        &self,
+        texture: &TextureDescription,
+        frame: usize,
+        size: (usize, usize),
+        data: Vec<T>,
+    ) -> Result<UploadToken> {
+        self.load_frame_region(texture, frame, (0, 0), size, data)
+    }
+
+    /// Loads part of one frame, see `load_image_region`.
+    pub fn load_frame_region<T: Sample>(
+        &self,
+        texture: &TextureDescription,
+        frame: usize,
+        offset: (usize, usize),
+        size: (usize, usize),
+        data: Vec<T>,
+    ) -> Result<UploadToken> {
+        if texture.kind.is_lut() {
+            return Err(Error::InvalidUpload("Image data for a LUT texture".into()));
+        }
+        self.load_texture(texture, frame, offset, size, data)
+    }
+
+    pub fn load_lut(&self, texture: &TextureDescription, data: Vec<u16>) -> Result<UploadToken> {
+        if texture.kind != TextureType::Lut {
+            return Err(Error::InvalidUpload(format!(
+                "Grey LUT data for a {:?} texture",
+                texture.kind
+            )));
+        }
         self.load_texture(
-            &texture.handle,
+            texture,
+            0,
+            (0, 0),
             (Self::LUT_TEXTURE_WIDTH, Self::LUT_TEXTURE_HEIGHT),
             data,
-        );
+        )
+    }
+
+    /// Loads RGBA colours, four samples per stored value, see `lut::Palette::apply`.
+    pub fn load_palette_lut(
+        &self,
+        texture: &TextureDescription,
+        data: Vec<u8>,
+    ) -> Result<UploadToken> {
+        if texture.kind != TextureType::PaletteLut {
+            return Err(Error::InvalidUpload(format!(
+                "Palette LUT data for a {:?} texture",
+                texture.kind
+            )));
+        }
+        self.load_texture(
+            texture,
+            0,
+            (0, 0),
+            (Self::LUT_TEXTURE_WIDTH, Self::LUT_TEXTURE_HEIGHT),
+            data,
+        )
     }
 
-    pub fn flush(&self) {
-        self.channel
-            .send(UploaderMessage::Flush)
-            .expect("Failed to send flush message");
+    /// Submits the queued GL commands, and builds the mip chains of frames that were
+    /// only loaded in part.
+    pub fn flush(&self) -> Result<()> {
+        self.send(UploaderMessage::Flush)
     }
-    pub fn release_texture(&self, texture: TextureHandle) {
-        self.channel
-            .send(UploaderMessage::ReleaseTexture(texture))
-            .expect("Failed to send release message");
+    /// Releases the texture right away instead of when the last clone is dropped.
+    /// Any remaining clones become stale and are rejected by loads and draws.
+    pub fn release_texture(&self, texture: TextureHandle) -> Result<()> {
+        self.send(UploaderMessage::ReleaseTexture(texture.texture().clone()))
     }
 
-    fn alloc_size(width: usize, height: usize) -> (usize, usize) {
-        // Compute the smallest power of 2 that contains the larger of width/height
-        let max_log2 = (width.max(height) as f32).log2().ceil();
-        let max_pow2 = 2.0_f32.powf(max_log2) as usize;
-        (max_pow2, max_pow2)
+    pub fn max_texture_size(&self) -> Result<usize> {
+        match self.request(UploaderMessage::GetMaxTextureSize)? {
+            UploaderMessage::MaxTextureSize(size) => Ok(size),
+            m => Err(Error::UnexpectedReply(format!("{:?}", m))),
+        }
     }
 
-    fn create_main_context() -> (EventLoop<()>, Context<PossiblyCurrent>, gst_gl::GLContext) {
-        // Create a new event loop on a thread different from the main thread.
-        let event_loop: EventLoop<()> = glutin::event_loop::EventLoop::new_any_thread();
-        // let window = glutin::window::WindowBuilder::new().with_title("GL rendering");
-        let main_context = glutin::ContextBuilder::new()
-            // .with_vsync(true)
-            .with_gl(glutin::GlRequest::Specific(glutin::Api::OpenGl, (4, 5)))
-            .with_gl_profile(glutin::GlProfile::Core)
-            .build_headless(&event_loop, PhysicalSize::new(100, 100))
-            // .build_windowed(window, &event_loop)
-            .expect("Failed to build window");
-        let main_context = unsafe {
-            main_context
-                .make_current()
-                .expect("Failed to make context current")
+    /// The most frames a multi-frame image can have.
+    pub fn max_texture_layers(&self) -> Result<usize> {
+        match self.request(UploaderMessage::GetMaxTextureLayers)? {
+            UploaderMessage::MaxTextureLayers(layers) => Ok(layers),
+            m => Err(Error::UnexpectedReply(format!("{:?}", m))),
+        }
+    }
+
+    /// Reads back what the GPU holds for the texture, for verification and export.
+    /// Returns the whole texture, `width` x `height` of the handle, not only the loaded part.
+    /// The frames of a multi-frame image follow each other.
+    /// Runs after every load sent before it.
+    pub fn read_image<T: Sample>(&self, texture: &TextureDescription) -> Result<Vec<T>> {
+        let handle = &texture.handle;
+        if !handle.is_current() {
+            return Err(Error::StaleTexture(handle.id));
+        }
+        if T::SAMPLE_TYPE != handle.format.sample_type() {
+            return Err(Error::SampleTypeMismatch(T::SAMPLE_TYPE, handle.format));
+        }
+        match self.request(UploaderMessage::ReadTexture(handle.texture().clone()))? {
+            UploaderMessage::TextureData(data) => T::from_data(data)
+                .ok_or_else(|| Error::UnexpectedReply("Texture data of the wrong type".into())),
+            m => Err(Error::UnexpectedReply(format!("{:?}", m))),
+        }
+    }
+
+    /// Acquires the textures for an image, split in tiles if it is larger than the GL limit.
+    /// Returns `None` when the tiles do not fit in the texture budget.
+    pub fn acquire_tiled_image(
+        &self,
+        format: PixelFormat,
+        size: (usize, usize),
+    ) -> Result<Option<TiledImageDescription>> {
+        let mut image = TiledImageDescription {
+            size,
+            tiles: Vec::new(),
         };
-        // Build gstreamer sharable context
-        let (gl_context, gl_display, platform) = match unsafe { main_context.raw_handle() } {
-            RawHandle::Wgl(wgl_context) => {
-                let gl_display = gst_gl::GLDisplay::new();
-                (
-                    wgl_context as usize,
-                    gl_display.upcast::<gst_gl::GLDisplay>(),
-                    gst_gl::GLPlatform::WGL,
-                )
+        for layout in tiles::tile_layout(size, self.max_texture_size()?) {
+            // Tiles are sized to the GL limit already, the allocation policy would round a
+            // tile at the limit past it.
+            let tile_size = layout.texture_size;
+            match self.acquire_frames(format, tile_size, tile_size, 1) {
+                Ok(Some(texture)) => image.tiles.push(ImageTile { layout, texture }),
+                // The tiles acquired so far are released as the partial image is dropped
+                failed => return failed.map(|_| None),
             }
+        }
+        Ok(Some(image))
+    }
+
+    /// Loads the full image data into all tiles of the image, returns one token per tile.
+    pub fn load_tiled_image<T: Sample>(
+        &self,
+        image: &TiledImageDescription,
+        data: &[T],
+    ) -> Result<Vec<UploadToken>> {
+        let channels = match image.tiles.first() {
+            Some(tile) => tile.texture.format().channels(),
+            None => return Ok(Vec::new()),
+        };
+        if image.size.0 * image.size.1 * channels != data.len() {
+            return Err(Error::InvalidUpload(format!(
+                "Expected {} samples for the image, got {}",
+                image.size.0 * image.size.1 * channels,
+                data.len()
+            )));
+        }
+        let mut tokens = Vec::with_capacity(image.tiles.len());
+        for tile in &image.tiles {
+            // Extract whole pixels, i.e. all the channels of each texel
+            let offset = tile.layout.texture_offset;
+            let size = tile.layout.texture_size;
+            let tile_data = tiles::extract_region(
+                data,
+                image.size.0 * channels,
+                (offset.0 * channels, offset.1),
+                (size.0 * channels, size.1),
+            );
+            tokens.push(self.load_image(&tile.texture, tile.layout.texture_size, tile_data)?);
+        }
+        Ok(tokens)
+    }
+
+    /// Releases the textures of all tiles right away, see `release_texture`.
+    pub fn release_tiled_image(&self, image: TiledImageDescription) -> Result<()> {
+        for tile in image.tiles {
+            self.release_texture(tile.texture.handle)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn create_event_loop() -> Result<EventLoop<()>> {
+        // winit panics without a display server, report it before that happens.
+        if std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+            return Err(Error::Context(format!(
+                "No X11 or Wayland display available, set {}=surfaceless (or osmesa) to run \
+                 without a display",
+                HeadlessBackend::ENV_VAR
+            )));
+        }
+        Ok(EventLoop::new_any_thread())
+    }
+
+    #[cfg(target_os = "windows")]
+    fn create_event_loop() -> Result<EventLoop<()>> {
+        Ok(EventLoop::new_any_thread())
+    }
+
+    fn build_context(
+        backend: HeadlessBackend,
+    ) -> Result<(Option<EventLoop<()>>, MainContext<NotCurrent>)> {
+        let builder = glutin::ContextBuilder::new()
+            .with_gl(glutin::GlRequest::Specific(glutin::Api::OpenGl, (4, 5)))
+            .with_gl_profile(glutin::GlProfile::Core);
+        let (event_loop, main_context) = match backend {
+            HeadlessBackend::Pbuffer => {
+                // Create a new event loop on a thread different from the main thread.
+                let event_loop = Self::create_event_loop()?;
+                let main_context = builder.build_headless(&event_loop, Self::HEADLESS_SIZE);
+                (Some(event_loop), main_context)
+            }
+            // Built on EGL directly, glutin would need a winit event loop and with it a
+            // display server.
+            #[cfg(target_os = "linux")]
+            HeadlessBackend::Surfaceless => {
+                let context = SurfacelessContext::new()?;
+                return Ok((None, MainContext::Surfaceless(Box::new(context))));
+            }
+            #[cfg(target_os = "linux")]
+            HeadlessBackend::OsMesa => (None, builder.build_osmesa(Self::HEADLESS_SIZE)),
             #[allow(unreachable_patterns)]
-            handler => panic!("Unsupported platform: {:?}.", handler),
+            backend => {
+                return Err(Error::Context(format!(
+                    "The {} backend is not supported on this platform",
+                    backend
+                )))
+            }
+        };
+        let main_context = main_context.map_err(|e| {
+            Error::Context(format!(
+                "Failed to build {} headless context: {}",
+                backend, e
+            ))
+        })?;
+        Ok((event_loop, MainContext::Glutin(main_context)))
+    }
+
+    fn create_main_context(backend: HeadlessBackend) -> Result<HeadlessContext> {
+        let (event_loop, main_context) = Self::build_context(backend)?;
+        let main_context = unsafe { main_context.make_current()? };
+        // Build gstreamer sharable context
+        let (gl_context, gl_display, platform) = match (&main_context, &event_loop) {
+            #[cfg(target_os = "linux")]
+            (MainContext::Surfaceless(context), _) => unsafe {
+                Self::wrap_egl(
+                    context.egl_display() as usize,
+                    context.egl_context() as usize,
+                )?
+            },
+            (MainContext::Glutin(context), Some(event_loop)) => unsafe {
+                Self::wrap_raw_handle(event_loop, context)?
+            },
+            // OSMesa has no display that GStreamer could share.
+            (MainContext::Glutin(_), None) => return Ok((None, main_context, None)),
         };
         // The shared gstreamer context will be moved into the sync bus handler.
         let shared_context = unsafe {
@@ -183,185 +950,902 @@ impl ThreadUploader {
                 gst_gl::GLAPI::OPENGL3,
             )
         }
-        .unwrap();
+        .ok_or_else(|| Error::Context("GStreamer failed to wrap the headless context".into()))?;
         shared_context
             .activate(true)
-            .expect("Couldn't activate wrapped GL context");
+            .map_err(|e| Error::Context(format!("Couldn't activate wrapped GL context: {}", e)))?;
         shared_context
             .fill_info()
-            .expect("Failed to fill context info");
+            .map_err(|e| Error::Context(format!("Failed to fill context info: {}", e)))?;
+
+        Ok((event_loop, main_context, Some(shared_context)))
+    }
 
-        (event_loop, main_context, shared_context)
+    #[cfg(target_os = "windows")]
+    unsafe fn wrap_raw_handle(
+        _event_loop: &EventLoop<()>,
+        main_context: &Context<PossiblyCurrent>,
+    ) -> Result<(usize, gst_gl::GLDisplay, gst_gl::GLPlatform)> {
+        match main_context.raw_handle() {
+            RawHandle::Wgl(wgl_context) => {
+                let gl_display = gst_gl::GLDisplay::new();
+                Ok((
+                    wgl_context as usize,
+                    gl_display.upcast::<gst_gl::GLDisplay>(),
+                    gst_gl::GLPlatform::WGL,
+                ))
+            }
+            #[allow(unreachable_patterns)]
+            handler => Err(Error::Context(format!(
+                "Unsupported platform: {:?}.",
+                handler
+            ))),
+        }
     }
 
+    #[cfg(target_os = "linux")]
+    unsafe fn wrap_raw_handle(
+        event_loop: &EventLoop<()>,
+        main_context: &Context<PossiblyCurrent>,
+    ) -> Result<(usize, gst_gl::GLDisplay, gst_gl::GLPlatform)> {
+        match main_context.raw_handle() {
+            RawHandle::Egl(egl_context) => {
+                let egl_display = main_context
+                    .get_egl_display()
+                    .ok_or_else(|| Error::Context("EGL context without an EGL display".into()))?;
+                Self::wrap_egl(egl_display as usize, egl_context as usize)
+            }
+            RawHandle::Glx(glx_context) => {
+                // The X connection is owned by the event loop, which outlives the context.
+                let xconnection = event_loop.xlib_xconnection().ok_or_else(|| {
+                    Error::Context("GLX context without an X11 connection".into())
+                })?;
+                let gl_display = gst_gl::GLDisplayX11::with_display(xconnection.display as usize)
+                    .map_err(|e| {
+                    Error::Context(format!("Failed to wrap the X11 display: {}", e))
+                })?;
+                Ok((
+                    glx_context as usize,
+                    gl_display.upcast::<gst_gl::GLDisplay>(),
+                    gst_gl::GLPlatform::GLX,
+                ))
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    unsafe fn wrap_egl(
+        egl_display: usize,
+        egl_context: usize,
+    ) -> Result<(usize, gst_gl::GLDisplay, gst_gl::GLPlatform)> {
+        // GStreamer has to use the same EGLDisplay as the context for the contexts to share.
+        let gl_display = gst_gl::GLDisplayEGL::with_egl_display(egl_display)
+            .map_err(|e| Error::Context(format!("Failed to wrap the EGL display: {}", e)))?;
+        Ok((
+            egl_context,
+            gl_display.upcast::<gst_gl::GLDisplay>(),
+            gst_gl::GLPlatform::EGL,
+        ))
+    }
+
+    // Creates the context and serves the shared context until told to make it current.
+    // Returns `None` if shut down before that.
     fn initial_setup(
-        channel: &BidirChannel<UploaderMessage>,
-    ) -> (EventLoop<()>, Context<PossiblyCurrent>) {
-        let (event_loop, main_context, shared_context) = Self::create_main_context();
+        receiver: &Receiver<Envelope>,
+        backend: HeadlessBackend,
+    ) -> Result<Option<HeadlessContext>> {
+        let (event_loop, main_context, shared_context) = Self::create_main_context(backend)?;
         // During setup we uncurrent the main_context and wait for a signal to proceeed
-        let main_context = unsafe {
-            main_context
-                .make_not_current()
-                .expect("Failed to uncurrent the main context")
-        };
-        let message = channel.recv().expect("Failed to get initial setup message");
-        match message {
-            UploaderMessage::GetContext => channel
-                .send(UploaderMessage::Context(shared_context))
-                .expect("Failed to reply with shared context"),
-            m => panic!("Expected GetContext got: {:?} during initial setup", m),
-        };
-        // Set the context as current and return
-        let message = channel
-            .recv()
-            .expect("Failed to get signal to current the context");
-        let main_context = match message {
-            UploaderMessage::SetContextCurrent => unsafe {
-                main_context
-                    .make_current()
-                    .expect("Failed to current the context")
-            },
-            e => panic!("Expected SetContextCurrent got: {:?}", e),
-        };
-        (event_loop, main_context)
+        let main_context = unsafe { main_context.make_not_current()? };
+        loop {
+            let envelope = match receiver.recv() {
+                Ok(envelope) => envelope,
+                // Every uploader was dropped during setup, there is no one to serve
+                Err(_) => return Ok(None),
+            };
+            match envelope.message {
+                UploaderMessage::GetContext => {
+                    envelope.respond(UploaderMessage::Context(shared_context.clone()))
+                }
+                UploaderMessage::SetContextCurrent => {
+                    let main_context = unsafe { main_context.make_current()? };
+                    return Ok(Some((event_loop, main_context, shared_context)));
+                }
+                UploaderMessage::Shutdown => return Ok(None),
+                _ => envelope.fail("The uploader context is not current yet".into()),
+            }
+        }
     }
 
-    fn thread_func(channel: BidirChannel<UploaderMessage>) {
-        let (event_loop, main_context) = Self::initial_setup(&channel);
+    // Fails every request until shut down, so no client waits forever.
+    fn fail_requests(receiver: &Receiver<Envelope>, error: Error) {
+        println!("Uploader setup failed: {}", error);
+        for envelope in receiver.iter() {
+            if let UploaderMessage::Shutdown = envelope.message {
+                return;
+            }
+            envelope.fail(error.to_string());
+        }
+    }
+
+    fn thread_func(receiver: Receiver<Envelope>, config: UploaderConfig) {
+        let (_event_loop, main_context, shared_context) =
+            match Self::initial_setup(&receiver, config.backend) {
+                Ok(Some(setup)) => setup,
+                Ok(None) => return,
+                Err(error) => return Self::fail_requests(&receiver, error),
+            };
         println!("Initial setup is complete entering dispatcher loop");
 
-        let mut texture_transfer = TextureTransfer::new(main_context);
+        let mut texture_transfer =
+            TextureTransfer::new(main_context, config.texture_budget, config.pixel_buffers);
 
+        let mut poll_interval = Self::FENCE_POLL_INTERVAL;
         loop {
-            let message = channel.recv().expect("Failed to recv message in thread");
-            let reply = match message {
-                UploaderMessage::AcquireTexture(size) => {
-                    let texture = texture_transfer.acquire_R16_texture(size.0, size.1);
-                    Some(UploaderMessage::Texture(texture))
+            let envelope = if texture_transfer.has_pending_uploads() {
+                // Keep checking the fences of the uploads in flight while idle, backing off
+                // while none of them completes so a slow GPU doesn't keep the thread busy.
+                match receiver.recv_timeout(poll_interval) {
+                    Ok(envelope) => envelope,
+                    Err(RecvTimeoutError::Timeout) => {
+                        poll_interval = match texture_transfer.poll_uploads() {
+                            0 => (poll_interval * 2).min(Self::MAX_FENCE_POLL_INTERVAL),
+                            _ => Self::FENCE_POLL_INTERVAL,
+                        };
+                        continue;
+                    }
+                    // All uploaders have been dropped
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            } else {
+                // Nothing to poll, sleep until the next request
+                poll_interval = Self::FENCE_POLL_INTERVAL;
+                match receiver.recv() {
+                    Ok(envelope) => envelope,
+                    Err(_) => break,
+                }
+            };
+            let Envelope { message, reply } = envelope;
+            let response = match message {
+                UploaderMessage::AcquireTexture(key) => {
+                    match texture_transfer.acquire_texture(key) {
+                        Ok(texture) => Some(UploaderMessage::Texture(texture)),
+                        Err(e) => Some(UploaderMessage::Fail(e.to_string())),
+                    }
                 }
                 UploaderMessage::LoadData(desc) => {
-                    texture_transfer.load_R16_texture(
-                        desc.texture,
-                        desc.data.size.0,
-                        desc.data.size.1,
-                        &desc.data.data,
-                    );
+                    texture_transfer.load_texture(desc);
                     None
                 }
                 UploaderMessage::ReleaseTexture(handle) => {
                     texture_transfer.release_texture(handle);
                     None
                 }
+                UploaderMessage::GetMaxTextureSize => Some(UploaderMessage::MaxTextureSize(
+                    texture_transfer.max_texture_size(),
+                )),
+                UploaderMessage::GetMaxTextureLayers => Some(UploaderMessage::MaxTextureLayers(
+                    texture_transfer.max_texture_layers(),
+                )),
+                UploaderMessage::ReadTexture(texture) => {
+                    match texture_transfer.read_texture(&texture) {
+                        Ok(data) => Some(UploaderMessage::TextureData(data)),
+                        Err(e) => Some(UploaderMessage::Fail(e.to_string())),
+                    }
+                }
                 UploaderMessage::Flush => {
                     texture_transfer.flush();
                     None
                 }
-                _ => panic!("Unexpected message type in main loop!"),
+                UploaderMessage::GetContext => {
+                    Some(UploaderMessage::Context(shared_context.clone()))
+                }
+                UploaderMessage::Shutdown => break,
+                // Only answered if someone is waiting for a reply
+                m => Some(UploaderMessage::Fail(format!(
+                    "Unexpected message in main loop: {:?}",
+                    m
+                ))),
             };
 
-            if let Some(reply) = reply {
-                channel.send(reply).expect("Failed to send reply");
+            if let (Some(response), Some(reply)) = (response, reply) {
+                // The requester may have given up waiting, that is fine.
+                let _ = reply.send(response);
             }
+            texture_transfer.poll_uploads();
         }
+        texture_transfer.shutdown();
+        // The context is destroyed when texture_transfer is dropped, before the event loop
+        // that owns the display connection (locals drop in reverse order).
+        println!("Uploader thread has shut down");
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct TextureHandle {
-    pub id: u32,
-    pub width: usize,
-    pub height: usize,
+// Staging buffer for uploads, reused once the upload that last used it has completed.
+struct PixelBuffer {
+    id: u32,
+    capacity: usize,
+    last_upload: Option<UploadToken>,
 }
 struct TextureTransfer {
-    _ctx: glutin::Context<PossiblyCurrent>, // Need to keep a ref to the context otherwise it gets deleted since it is moved in the new() method
+    _ctx: MainContext<PossiblyCurrent>, // Need to keep a ref to the context otherwise it gets deleted since it is moved in the new() method
     bindings: gl::Gl,
+    pool: TexturePool,
+    pixel_buffers: Vec<PixelBuffer>,
+    next_pixel_buffer: usize,
+    // Uploads whose fence has not signaled yet
+    pending: Vec<UploadToken>,
+    // Every texture created and not yet deleted, in use or pooled
+    textures: HashMap<u32, Arc<TextureSlot>>,
+    // Loads into the layers of mipmapped textures since their chain was last built, by
+    // texture and layer
+    stale_mipmaps: HashMap<(u32, usize), (GlTexture, ValidRegion)>,
 }
 
 impl TextureTransfer {
-    pub fn new(ctx: glutin::Context<PossiblyCurrent>) -> Self {
+    const UPLOAD_TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn new(
+        ctx: MainContext<PossiblyCurrent>,
+        texture_budget: usize,
+        pixel_buffer_count: usize,
+    ) -> Self {
         let bindings = gl::Gl::load_with(|name| ctx.get_proc_address(name) as _);
         println!("Loaded bindings for main context");
+        let pixel_buffers = unsafe {
+            // Rows are tightly packed, whatever the width of the image.
+            bindings.PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            bindings.PixelStorei(gl::PACK_ALIGNMENT, 1);
+            // The buffer storage is allocated on first use.
+            let mut ids = vec![0_u32; pixel_buffer_count];
+            if pixel_buffer_count > 0 {
+                bindings.CreateBuffers(pixel_buffer_count as _, ids.as_mut_ptr());
+            }
+            ids.into_iter()
+                .map(|id| PixelBuffer {
+                    id,
+                    capacity: 0,
+                    last_upload: None,
+                })
+                .collect()
+        };
         Self {
             bindings,
             _ctx: ctx,
+            pool: TexturePool::new(texture_budget),
+            pixel_buffers,
+            next_pixel_buffer: 0,
+            pending: Vec::new(),
+            textures: HashMap::new(),
+            stale_mipmaps: HashMap::new(),
         }
     }
 
-    unsafe fn create_R16_texture(&self, width: usize, height: usize) -> TextureHandle {
+    // Every texture is an array, single images and LUTs have one layer. The shaders then
+    // only need one sampler type, and frames are selected without rebinding.
+    unsafe fn create_texture(&mut self, key: PoolKey) -> GlTexture {
+        let PoolKey {
+            format,
+            width,
+            height,
+            layers,
+            mipmaps,
+        } = key;
         let mut texture_id = mem::MaybeUninit::uninit();
         self.bindings.GenTextures(1, texture_id.as_mut_ptr());
         let texture_id = texture_id.assume_init();
-        self.bindings.BindTexture(gl::TEXTURE_2D, texture_id);
+        let slot = Arc::new(TextureSlot::default());
+        self.textures.insert(texture_id, slot.clone());
+        self.bindings.BindTexture(gl::TEXTURE_2D_ARRAY, texture_id);
         // Set texture filter params
-        self.bindings
-            .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as _);
-        self.bindings
-            .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as _);
-        self.bindings
-            .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as _);
-        self.bindings
-            .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as _);
-        // Create the Texture object empty
-        self.bindings.TexImage2D(
-            gl::TEXTURE_2D,
-            0,
-            gl::R16 as _,
+        self.bindings.TexParameteri(
+            gl::TEXTURE_2D_ARRAY,
+            gl::TEXTURE_WRAP_S,
+            gl::CLAMP_TO_EDGE as _,
+        );
+        self.bindings.TexParameteri(
+            gl::TEXTURE_2D_ARRAY,
+            gl::TEXTURE_WRAP_T,
+            gl::CLAMP_TO_EDGE as _,
+        );
+        self.bindings.TexParameteri(
+            gl::TEXTURE_2D_ARRAY,
+            gl::TEXTURE_MIN_FILTER,
+            gl::LINEAR as _,
+        );
+        self.bindings.TexParameteri(
+            gl::TEXTURE_2D_ARRAY,
+            gl::TEXTURE_MAG_FILTER,
+            gl::LINEAR as _,
+        );
+        // Create the Texture object empty. The storage is immutable so single layers
+        // can be viewed as 2D textures to build their mip chain.
+        self.bindings.TexStorage3D(
+            gl::TEXTURE_2D_ARRAY,
+            key.levels() as _,
+            format.gl_internal_format(),
             width as _,
             height as _,
-            0,
-            gl::RED,
-            gl::UNSIGNED_SHORT,
-            ptr::null(),
+            layers as _,
         );
-        self.bindings.BindTexture(gl::TEXTURE_2D, 0);
+        self.bindings.BindTexture(gl::TEXTURE_2D_ARRAY, 0);
 
-        TextureHandle {
+        GlTexture {
             id: texture_id,
             width,
             height,
+            layers,
+            format,
+            mipmaps,
+            generation: slot.generation(),
+            slot,
         }
     }
 
-    fn acquire_R16_texture(&mut self, width: usize, height: usize) -> Option<TextureHandle> {
-        // Maybe check for memory contraints, but for now we just allocate.
-        unsafe { Some(self.create_R16_texture(width, height)) }
+    fn acquire_texture(&mut self, key: PoolKey) -> Result<Option<GlTexture>> {
+        if let Some(id) = self.pool.take(&key) {
+            // Released textures have been invalidated, this is the next generation
+            let slot = self.textures[&id].clone();
+            return Ok(Some(GlTexture {
+                id,
+                width: key.width,
+                height: key.height,
+                layers: key.layers,
+                format: key.format,
+                mipmaps: key.mipmaps,
+                generation: slot.generation(),
+                slot,
+            }));
+        }
+        // Make room for the new texture, or give up if it would exceed the budget.
+        let evicted = match self.pool.reserve(&key) {
+            Some(evicted) => evicted,
+            None => return Ok(None),
+        };
+        for id in evicted {
+            self.delete_texture(id);
+        }
+        let texture = unsafe { self.create_texture(key) };
+        let error = unsafe { self.bindings.GetError() };
+        if error != gl::NO_ERROR {
+            // Typically GL_OUT_OF_MEMORY, the driver has less room than the budget assumes
+            self.delete_texture(texture.id);
+            self.pool.unreserve(&key);
+            return Err(Error::Gl(format!(
+                "Failed to create {}x{}x{} {:?} texture, GL error 0x{:x}",
+                key.width, key.height, key.layers, key.format, error
+            )));
+        }
+        Ok(Some(texture))
     }
 
-    fn load_R16_texture(
-        &self,
-        texture: TextureHandle,
-        width: usize,
-        height: usize,
-        image_data: &[u16],
-    ) {
-        assert!(width <= texture.width && height <= texture.height);
-        assert!(image_data.len() == width * height);
+    fn load_texture(&mut self, desc: LoadDescription) {
+        let LoadDescription {
+            texture,
+            image_size,
+            data,
+            token,
+        } = desc;
+        let (layer, region, image_data) =
+            (data.layer, Region::new(data.offset, data.size), &data.data);
+        // Released while the load was queued, the GL name may belong to another texture now
+        if !texture.is_current() {
+            token.fail(Error::StaleTexture(texture.id).to_string());
+            return;
+        }
+        // The client checks loads before sending them, this guards the GL call
+        if let Err(e) = Self::check_load(&texture, layer, region, image_data) {
+            token.fail(e.to_string());
+            return;
+        }
+        let format = texture.format;
+        let (width, height) = region.size;
         unsafe {
-            self.bindings.TextureSubImage2D(
+            // Either a client pointer or an offset into the bound pixel buffer
+            let pixels = match self.stage_in_pixel_buffer(image_data, &token) {
+                Ok(Some(offset)) => offset,
+                Ok(None) => image_data.sample_ptr(0),
+                Err(e) => {
+                    token.fail(e.to_string());
+                    return;
+                }
+            };
+            self.bindings.TextureSubImage3D(
                 texture.id,
                 0,
-                0,
-                0,
+                region.offset.0 as _,
+                region.offset.1 as _,
+                layer as _,
                 width as _,
                 height as _,
-                gl::RED,
-                gl::UNSIGNED_SHORT,
-                image_data.as_ptr() as _,
+                1,
+                format.gl_format(),
+                format.gl_type(),
+                pixels,
             );
+            self.replicate_edges(&texture, image_size, layer, region, pixels);
+            self.bindings.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+            if texture.mipmaps {
+                self.update_mipmaps(&texture, image_size, layer, region);
+            }
+
+            // Signal the token when the GPU is done with the upload
+            let fence = self.bindings.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+            self.bindings.Flush();
+            token.set_fence(fence as usize);
         }
+        self.pending.push(token);
     }
 
-    fn release_texture(&self, texture: TextureHandle) {
+    // The chain is built once the loads since the last build cover the image, or at the
+    // next flush, so an image loaded in many parts costs a single build.
+    unsafe fn update_mipmaps(
+        &mut self,
+        texture: &GlTexture,
+        image_size: (usize, usize),
+        layer: usize,
+        region: Region,
+    ) {
+        let key = (texture.id, layer);
+        let (_, loaded) = self
+            .stale_mipmaps
+            .entry(key)
+            .or_insert_with(|| (texture.clone(), ValidRegion::default()));
+        loaded.add(region);
+        if loaded.covers(&Region::new((0, 0), image_size)) {
+            self.stale_mipmaps.remove(&key);
+            self.generate_layer_mipmaps(texture, layer);
+        }
+    }
+
+    // Rebuilds the whole chain of one layer. The other layers
+    // are left alone, so loading every frame of an image stays linear in its size.
+    unsafe fn generate_layer_mipmaps(&self, texture: &GlTexture, layer: usize) {
+        let levels = PoolKey::new(texture.format, texture.width, texture.height)
+            .with_mipmaps(true)
+            .levels();
+        let mut view = mem::MaybeUninit::uninit();
+        self.bindings.GenTextures(1, view.as_mut_ptr());
+        let view = view.assume_init();
+        self.bindings.TextureView(
+            view,
+            gl::TEXTURE_2D,
+            texture.id,
+            texture.format.gl_internal_format(),
+            0,
+            levels as _,
+            layer as _,
+            1,
+        );
+        self.bindings.GenerateTextureMipmap(view);
+        let view = mem::MaybeUninit::new(view);
+        self.bindings.DeleteTextures(1, view.as_ptr());
+    }
+
+    fn check_load(
+        texture: &GlTexture,
+        layer: usize,
+        region: Region,
+        image_data: &SampleData,
+    ) -> Result<()> {
+        let format = texture.format;
+        let end = region.end();
+        if end.0 > texture.width || end.1 > texture.height || layer >= texture.layers {
+            return Err(Error::InvalidUpload(format!(
+                "Region ending at {:?} in layer {} is outside the {}x{}x{} texture",
+                end, layer, texture.width, texture.height, texture.layers
+            )));
+        }
+        if image_data.sample_type() != format.sample_type() {
+            return Err(Error::SampleTypeMismatch(image_data.sample_type(), format));
+        }
+        if image_data.len() != format.sample_count(region.size) {
+            return Err(Error::InvalidUpload(format!(
+                "Expected {} samples for the region, got {}",
+                format.sample_count(region.size),
+                image_data.len()
+            )));
+        }
+        Ok(())
+    }
+
+    // Copies the data into the next pixel buffer of the ring and leaves it bound as the
+    // unpack buffer. Returns the offset to upload from, or None without pixel buffers.
+    unsafe fn stage_in_pixel_buffer(
+        &mut self,
+        image_data: &SampleData,
+        token: &UploadToken,
+    ) -> Result<Option<*const c_void>> {
+        if self.pixel_buffers.is_empty() {
+            return Ok(None);
+        }
+        let index = self.next_pixel_buffer;
+        self.next_pixel_buffer = (index + 1) % self.pixel_buffers.len();
+        // Don't overwrite data the GPU may still be reading
+        if let Some(previous) = self.pixel_buffers[index].last_upload.replace(token.clone()) {
+            self.wait_upload(&previous)?;
+        }
+
+        let bytes = image_data.byte_len();
+        let buffer = &mut self.pixel_buffers[index];
+        if buffer.capacity < bytes {
+            self.bindings
+                .NamedBufferData(buffer.id, bytes as _, ptr::null(), gl::STREAM_DRAW);
+            buffer.capacity = bytes;
+        }
+        let mapped = self.bindings.MapNamedBufferRange(
+            buffer.id,
+            0,
+            bytes as _,
+            gl::MAP_WRITE_BIT | gl::MAP_INVALIDATE_BUFFER_BIT,
+        );
+        if mapped.is_null() {
+            return Err(Error::Gl(format!(
+                "Failed to map pixel buffer of {} bytes",
+                bytes
+            )));
+        }
+        ptr::copy_nonoverlapping(
+            image_data.sample_ptr(0) as *const u8,
+            mapped as *mut u8,
+            bytes,
+        );
+        self.bindings.UnmapNamedBuffer(buffer.id);
+        self.bindings.BindBuffer(gl::PIXEL_UNPACK_BUFFER, buffer.id);
+        Ok(Some(ptr::null()))
+    }
+
+    fn has_pending_uploads(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    // Completes the tokens of all uploads whose fence has signaled, returns how many.
+    fn poll_uploads(&mut self) -> usize {
+        let bindings = &self.bindings;
+        let pending = self.pending.len();
+        self.pending.retain(|token| {
+            let fence = match token.pending_fence() {
+                Some(fence) => fence as gl::types::GLsync,
+                None => return false,
+            };
+            let status = unsafe { bindings.ClientWaitSync(fence, 0, 0) };
+            if status == gl::ALREADY_SIGNALED || status == gl::CONDITION_SATISFIED {
+                Self::complete_upload(bindings, token);
+                false
+            } else {
+                true
+            }
+        });
+        pending - self.pending.len()
+    }
+
+    // Blocks until the upload has completed, an upload that takes longer than
+    // `UPLOAD_TIMEOUT` is failed.
+    fn wait_upload(&self, token: &UploadToken) -> Result<()> {
+        if let Some(fence) = token.pending_fence() {
+            let status = unsafe {
+                self.bindings.ClientWaitSync(
+                    fence as gl::types::GLsync,
+                    gl::SYNC_FLUSH_COMMANDS_BIT,
+                    Self::UPLOAD_TIMEOUT.as_nanos() as u64,
+                )
+            };
+            let error = match status {
+                gl::ALREADY_SIGNALED | gl::CONDITION_SATISFIED => {
+                    Self::complete_upload(&self.bindings, token);
+                    return Ok(());
+                }
+                gl::TIMEOUT_EXPIRED => Error::Gl(format!(
+                    "The upload did not complete within {} s",
+                    Self::UPLOAD_TIMEOUT.as_secs()
+                )),
+                _ => Error::Gl("Failed to wait for upload fence".into()),
+            };
+            if let Some(fence) = token.fail(error.to_string()) {
+                unsafe { self.bindings.DeleteSync(fence as gl::types::GLsync) };
+            }
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    fn complete_upload(bindings: &gl::Gl, token: &UploadToken) {
+        if let Some(fence) = token.complete() {
+            unsafe { bindings.DeleteSync(fence as gl::types::GLsync) };
+        }
+    }
+
+    // When the texture is larger than the image the last column and row are repeated into
+    // the padding, otherwise filtering at the image edge blends in undefined texels.
+    // Bicubic interpolation reads as far as the tile borders, `tiles::BORDER` texels.
+    // Only loads that reach the last column or row of the image have anything to repeat.
+    // `pixels` is the start of the region data, in client memory or the bound pixel buffer.
+    unsafe fn replicate_edges(
+        &self,
+        texture: &GlTexture,
+        image_size: (usize, usize),
+        layer: usize,
+        region: Region,
+        pixels: *const c_void,
+    ) {
+        if region.is_empty() {
+            return;
+        }
+        let (width, height) = region.size;
+        let (x0, y0) = region.offset;
+        let (x1, y1) = region.end();
+        // Number of padding columns and rows to write
+        let pad = |end: usize, image: usize, texture: usize| match end == image {
+            true => (texture - end).min(tiles::BORDER),
+            false => 0,
+        };
+        let pad_x = pad(x1, image_size.0, texture.width);
+        let pad_y = pad(y1, image_size.1, texture.height);
+        // (x, y, width, height, first texel) of the regions to write
+        let mut edges = Vec::new();
+        for i in 0..pad_x {
+            edges.push((x1 + i, y0, 1, height, width - 1));
+        }
+        for j in 0..pad_y {
+            edges.push((x0, y1 + j, width, 1, (height - 1) * width));
+        }
+        for i in 0..pad_x {
+            for j in 0..pad_y {
+                edges.push((x1 + i, y1 + j, 1, 1, width * height - 1));
+            }
+        }
+        self.bindings.PixelStorei(gl::UNPACK_ROW_LENGTH, width as _);
+        for (x, y, w, h, first) in edges {
+            self.bindings.TextureSubImage3D(
+                texture.id,
+                0,
+                x as _,
+                y as _,
+                layer as _,
+                w as _,
+                h as _,
+                1,
+                texture.format.gl_format(),
+                texture.format.gl_type(),
+                (pixels as usize + first * texture.format.bytes_per_pixel()) as *const c_void,
+            );
+        }
+        self.bindings.PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+    }
+
+    fn release_texture(&mut self, texture: GlTexture) {
+        // A forced release followed by the drop of the last handle releases twice
+        if !texture.is_current() {
+            return;
+        }
+        texture.slot.invalidate();
+        self.stale_mipmaps.retain(|(id, _), _| *id != texture.id);
+        // Keep the texture around, the next acquire of the same size reuses it.
+        let key = PoolKey::layered(
+            texture.format,
+            texture.width,
+            texture.height,
+            texture.layers,
+        )
+        .with_mipmaps(texture.mipmaps);
+        self.pool.put(key, texture.id);
+    }
+
+    fn delete_texture(&mut self, texture_id: u32) {
+        if let Some(slot) = self.textures.remove(&texture_id) {
+            slot.invalidate();
+        }
         unsafe {
-            let texture_id = mem::MaybeUninit::new(texture.id);
+            let texture_id = mem::MaybeUninit::new(texture_id);
             self.bindings.DeleteTextures(1, texture_id.as_ptr());
         }
     }
 
-    pub fn flush(&self) {
+    fn max_texture_size(&self) -> usize {
+        let mut size: gl::types::GLint = 0;
         unsafe {
+            self.bindings.GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut size);
+        }
+        size as usize
+    }
+
+    fn max_texture_layers(&self) -> usize {
+        let mut layers: gl::types::GLint = 0;
+        unsafe {
+            self.bindings
+                .GetIntegerv(gl::MAX_ARRAY_TEXTURE_LAYERS, &mut layers);
+        }
+        layers as usize
+    }
+
+    fn read_texture(&self, texture: &GlTexture) -> Result<SampleData> {
+        if !texture.is_current() {
+            return Err(Error::StaleTexture(texture.id));
+        }
+        let format = texture.format;
+        let mut data = SampleData::zeroed(
+            format.sample_type(),
+            format.sample_count((texture.width, texture.height)) * texture.layers,
+        );
+        unsafe {
+            // Earlier loads are on this context, GL orders the read after them.
+            self.bindings.GetTextureImage(
+                texture.id,
+                0,
+                format.gl_format(),
+                format.gl_type(),
+                data.byte_len() as _,
+                data.as_mut_ptr(),
+            );
+            let error = self.bindings.GetError();
+            if error != gl::NO_ERROR {
+                return Err(Error::Gl(format!(
+                    "Failed to read texture {}: GL error 0x{:x}",
+                    texture.id, error
+                )));
+            }
+        }
+        Ok(data)
+    }
+
+    pub fn flush(&mut self) {
+        let stale: Vec<_> = self.stale_mipmaps.drain().collect();
+        unsafe {
+            for ((_, layer), (texture, _)) in stale {
+                // Released since the load, the GL name may belong to another texture now
+                if texture.is_current() {
+                    self.generate_layer_mipmaps(&texture, layer);
+                }
+            }
             // Make sure to flush the command queue
             self.bindings.Flush();
         }
     }
+
+    // Deletes every GL object we own, handles still held by clients become stale.
+    fn shutdown(&mut self) {
+        unsafe {
+            // Let the uploads in flight finish so their tokens can complete
+            self.bindings.Finish();
+        }
+        for token in mem::take(&mut self.pending) {
+            Self::complete_upload(&self.bindings, &token);
+        }
+        self.pool.drain();
+        let textures: Vec<u32> = self
+            .textures
+            .drain()
+            .map(|(id, slot)| {
+                slot.invalidate();
+                id
+            })
+            .collect();
+        let buffers: Vec<u32> = self.pixel_buffers.drain(..).map(|b| b.id).collect();
+        unsafe {
+            if !textures.is_empty() {
+                self.bindings
+                    .DeleteTextures(textures.len() as _, textures.as_ptr());
+            }
+            if !buffers.is_empty() {
+                self.bindings
+                    .DeleteBuffers(buffers.len() as _, buffers.as_ptr());
+            }
+        }
+        println!(
+            "Released {} textures and {} pixel buffers",
+            textures.len(),
+            buffers.len()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uploader_is_shareable_between_threads() {
+        fn assert_shareable<T: Send + Sync + Clone>() {}
+        assert_shareable::<ThreadUploader>();
+    }
+
+    #[test]
+    fn synced_loads_count_as_valid() {
+        let slot = Arc::new(TextureSlot::default());
+        let texture = GlTexture {
+            id: 1,
+            width: 4,
+            height: 4,
+            layers: 1,
+            format: PixelFormat::R16,
+            mipmaps: false,
+            generation: slot.generation(),
+            slot,
+        };
+        let handle = TextureHandle::new(texture, |_| ());
+        let description = TextureDescription::new(handle, TextureType::Mono, (4, 4));
+        let (top, bottom) = (Region::new((0, 0), (4, 2)), Region::new((0, 2), (4, 2)));
+        let (first, second) = (UploadToken::new(), UploadToken::new());
+        description.track_load(0, top, &first);
+        description.track_load(0, bottom, &second);
+        assert!(description.valid_region(0).unwrap().rects().is_empty());
+        assert!(description.valid_region(1).is_none());
+        // Waited for by the renderer before the uploader has seen the fence signal
+        let synced = description
+            .synced_region(0, std::slice::from_ref(&first))
+            .unwrap();
+        assert!(synced.covers(&top));
+        assert!(!synced.covers(&bottom));
+        second.complete();
+        let valid = description.valid_region(0).unwrap();
+        assert!(valid.covers(&bottom));
+        assert!(!valid.covers(&top));
+    }
+
+    #[test]
+    fn replies_reach_their_requester() {
+        // Answers every texture request with its width, no GL needed
+        let uploader = ThreadUploader::spawn_thread(AllocationPolicy::Exact, false, |receiver| {
+            for envelope in receiver.iter() {
+                match envelope.message {
+                    UploaderMessage::AcquireTexture(key) => {
+                        envelope.respond(UploaderMessage::MaxTextureSize(key.width))
+                    }
+                    UploaderMessage::Shutdown => return,
+                    _ => envelope.fail("Unexpected message".into()),
+                }
+            }
+        });
+        let threads: Vec<_> = (1..=8)
+            .map(|width| {
+                let uploader = uploader.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        let message = UploaderMessage::AcquireTexture(PoolKey::new(
+                            PixelFormat::R16,
+                            width,
+                            1,
+                        ));
+                        let reply = match uploader.request(message).unwrap() {
+                            UploaderMessage::MaxTextureSize(reply) => Some(reply),
+                            _ => None,
+                        };
+                        assert_eq!(reply, Some(width));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        uploader.shutdown().unwrap();
+    }
+
+    // Needs a GL 4.5 context, run with `cargo test -- --ignored`.
+    // Set GLTEST_GL_BACKEND=osmesa on machines without a display.
+    #[test]
+    #[ignore]
+    fn r16_upload_round_trip() {
+        let uploader = ThreadUploader::with_config(UploaderConfig {
+            backend: HeadlessBackend::from_env().unwrap(),
+            allocation: AllocationPolicy::Exact,
+            ..Default::default()
+        })
+        .unwrap();
+        uploader.set_current().unwrap();
+        let texture = uploader
+            .acquire_image_handle(PixelFormat::R16, (5, 3))
+            .unwrap()
+            .unwrap();
+        let data: Vec<u16> = (0..15).map(|i| i * 4369).collect();
+        let token = uploader.load_image(&texture, (5, 3), data.clone()).unwrap();
+        token.wait().unwrap();
+        assert_eq!(uploader.read_image::<u16>(&texture).unwrap(), data);
+        assert!(matches!(
+            uploader.read_image::<u8>(&texture),
+            Err(Error::SampleTypeMismatch(..))
+        ));
+        drop(texture);
+        uploader.shutdown().unwrap();
+    }
 }
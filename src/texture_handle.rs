@@ -0,0 +1,142 @@
+use crate::pixel_format::PixelFormat;
+use std::{
+    fmt,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// Generation counter of one GL texture name. The uploader thread bumps it every time the
+/// texture is released or deleted, which makes every `GlTexture` handed out before stale.
+#[derive(Debug, Default)]
+pub struct TextureSlot {
+    generation: AtomicU64,
+}
+
+impl TextureSlot {
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Invalidates the textures handed out so far, returns the new generation.
+    pub fn invalidate(&self) -> u64 {
+        self.generation.fetch_add(1, Ordering::AcqRel) + 1
+    }
+}
+
+/// A GL texture as handed out by the uploader thread, always a `GL_TEXTURE_2D_ARRAY`.
+/// This does not own the texture, see `TextureHandle` for that.
+#[derive(Debug, Clone)]
+pub struct GlTexture {
+    pub id: u32,
+    pub width: usize,
+    pub height: usize,
+    /// Number of frames, 1 for a single image.
+    pub layers: usize,
+    pub format: PixelFormat,
+    /// The texture has a mip chain, regenerated by every load.
+    pub mipmaps: bool,
+    pub generation: u64,
+    pub(crate) slot: Arc<TextureSlot>,
+}
+
+impl GlTexture {
+    /// False once the texture has been released, the GL name may then belong to another texture.
+    pub fn is_current(&self) -> bool {
+        self.slot.generation() == self.generation
+    }
+}
+
+/// Owns a texture of the uploader. Clones share the texture, when the last clone is
+/// dropped the texture is given back to the uploader thread.
+#[derive(Clone)]
+pub struct TextureHandle {
+    inner: Arc<HandleInner>,
+}
+
+struct HandleInner {
+    texture: GlTexture,
+    release: Box<dyn Fn(GlTexture) + Send + Sync>,
+}
+
+impl TextureHandle {
+    /// `release` is called with the texture when the last clone is dropped.
+    pub fn new(texture: GlTexture, release: impl Fn(GlTexture) + Send + Sync + 'static) -> Self {
+        Self {
+            inner: Arc::new(HandleInner {
+                texture,
+                release: Box::new(release),
+            }),
+        }
+    }
+
+    pub fn texture(&self) -> &GlTexture {
+        &self.inner.texture
+    }
+}
+
+impl Deref for TextureHandle {
+    type Target = GlTexture;
+
+    fn deref(&self) -> &GlTexture {
+        &self.inner.texture
+    }
+}
+
+impl fmt::Debug for TextureHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TextureHandle")
+            .field(&self.inner.texture)
+            .finish()
+    }
+}
+
+impl Drop for HandleInner {
+    fn drop(&mut self) {
+        (self.release)(self.texture.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn texture(slot: &Arc<TextureSlot>) -> GlTexture {
+        GlTexture {
+            id: 1,
+            width: 16,
+            height: 16,
+            layers: 1,
+            format: PixelFormat::R16,
+            mipmaps: false,
+            generation: slot.generation(),
+            slot: slot.clone(),
+        }
+    }
+
+    #[test]
+    fn last_clone_releases() {
+        let released = Arc::new(Mutex::new(Vec::new()));
+        let sink = released.clone();
+        let slot = Arc::new(TextureSlot::default());
+        let handle = TextureHandle::new(texture(&slot), move |t| sink.lock().unwrap().push(t.id));
+        let clone = handle.clone();
+        drop(handle);
+        assert!(released.lock().unwrap().is_empty());
+        drop(clone);
+        assert_eq!(*released.lock().unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn invalidated_texture_is_stale() {
+        let slot = Arc::new(TextureSlot::default());
+        let old = texture(&slot);
+        assert!(old.is_current());
+        slot.invalidate();
+        assert!(!old.is_current());
+        assert!(texture(&slot).is_current());
+    }
+}
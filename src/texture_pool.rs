@@ -0,0 +1,248 @@
+use crate::{
+    error::{Error, Result},
+    pixel_format::PixelFormat,
+};
+use std::collections::VecDeque;
+
+/// How the texture size is rounded up from the image size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AllocationPolicy {
+    /// The texture has the exact size of the image.
+    Exact,
+    /// Square power of two textures, few distinct sizes so the pool recycles well.
+    PowerOfTwo,
+    /// Each dimension rounded up to a multiple of the given number of texels, which has
+    /// to be a power of two.
+    Aligned(usize),
+}
+
+impl AllocationPolicy {
+    pub fn validate(&self) -> Result<()> {
+        match *self {
+            AllocationPolicy::Aligned(alignment) if !alignment.is_power_of_two() => {
+                Err(Error::InvalidConfig(format!(
+                    "The texture alignment has to be a power of two, got {}",
+                    alignment
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn alloc_size(&self, width: usize, height: usize) -> (usize, usize) {
+        match *self {
+            AllocationPolicy::Exact => (width, height),
+            AllocationPolicy::PowerOfTwo => {
+                // Compute the smallest power of 2 that contains the larger of width/height
+                let max_pow2 = width.max(height).next_power_of_two();
+                (max_pow2, max_pow2)
+            }
+            AllocationPolicy::Aligned(alignment) => {
+                let align = |n: usize| (n + alignment - 1) / alignment * alignment;
+                (align(width), align(height))
+            }
+        }
+    }
+}
+
+impl Default for AllocationPolicy {
+    fn default() -> Self {
+        AllocationPolicy::PowerOfTwo
+    }
+}
+
+/// Textures are only recycled between requests with the same key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PoolKey {
+    pub format: PixelFormat,
+    pub width: usize,
+    pub height: usize,
+    pub layers: usize,
+    /// The texture has a full mip chain, which takes up room in the budget too.
+    pub mipmaps: bool,
+}
+
+impl PoolKey {
+    pub fn new(format: PixelFormat, width: usize, height: usize) -> Self {
+        Self::layered(format, width, height, 1)
+    }
+
+    pub fn layered(format: PixelFormat, width: usize, height: usize, layers: usize) -> Self {
+        Self {
+            format,
+            width,
+            height,
+            layers,
+            mipmaps: false,
+        }
+    }
+
+    pub fn with_mipmaps(self, mipmaps: bool) -> Self {
+        Self { mipmaps, ..self }
+    }
+
+    /// Number of mip levels, down to 1x1 for a mip chain.
+    pub fn levels(&self) -> usize {
+        if !self.mipmaps {
+            return 1;
+        }
+        let mut levels = 1;
+        while self.width.max(self.height) >> levels > 0 {
+            levels += 1;
+        }
+        levels
+    }
+
+    pub fn byte_size(&self) -> usize {
+        let texels: usize = (0..self.levels())
+            .map(|level| (self.width >> level).max(1) * (self.height >> level).max(1))
+            .sum();
+        self.format.bytes_per_pixel() * texels * self.layers
+    }
+}
+
+/// Keeps released textures around for reuse, within a byte budget.
+/// The pool only does the bookkeeping, creating and deleting the GL objects is up to the caller.
+#[derive(Debug)]
+pub struct TexturePool {
+    budget: usize,
+    // Bytes held by every texture we know of, both in use and free.
+    allocated: usize,
+    // Released textures, least recently released first.
+    free: VecDeque<(PoolKey, u32)>,
+}
+
+impl TexturePool {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            allocated: 0,
+            free: VecDeque::new(),
+        }
+    }
+
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    /// Takes the most recently released texture matching `key`.
+    pub fn take(&mut self, key: &PoolKey) -> Option<u32> {
+        let index = self.free.iter().rposition(|(k, _)| k == key)?;
+        self.free.remove(index).map(|(_, id)| id)
+    }
+
+    /// Reserves room for a new texture. On success the returned textures have been
+    /// evicted (least recently released first) and must be deleted by the caller.
+    /// Returns `None`, and evicts nothing, when the texture can not fit in the budget.
+    pub fn reserve(&mut self, key: &PoolKey) -> Option<Vec<u32>> {
+        let needed = key.byte_size();
+        let free_bytes: usize = self.free.iter().map(|(k, _)| k.byte_size()).sum();
+        if self.allocated - free_bytes + needed > self.budget {
+            return None;
+        }
+        let mut evicted = Vec::new();
+        while self.allocated + needed > self.budget {
+            let (key, id) = self.free.pop_front()?;
+            self.allocated -= key.byte_size();
+            evicted.push(id);
+        }
+        self.allocated += needed;
+        Some(evicted)
+    }
+
+    /// Gives back a reservation whose texture could not be created.
+    pub fn unreserve(&mut self, key: &PoolKey) {
+        self.allocated -= key.byte_size();
+    }
+
+    /// Returns a texture to the pool so it can be handed out again.
+    pub fn put(&mut self, key: PoolKey, id: u32) {
+        self.free.push_back((key, id));
+    }
+
+    /// Empties the pool, the returned textures must be deleted by the caller.
+    pub fn drain(&mut self) -> Vec<u32> {
+        let freed: usize = self.free.iter().map(|(k, _)| k.byte_size()).sum();
+        self.allocated -= freed;
+        self.free.drain(..).map(|(_, id)| id).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocation_policies() {
+        assert_eq!(AllocationPolicy::Exact.alloc_size(300, 17), (300, 17));
+        assert_eq!(AllocationPolicy::PowerOfTwo.alloc_size(300, 17), (512, 512));
+        assert_eq!(
+            AllocationPolicy::PowerOfTwo.alloc_size(256, 256),
+            (256, 256)
+        );
+        assert_eq!(AllocationPolicy::Aligned(64).alloc_size(300, 64), (320, 64));
+    }
+
+    #[test]
+    fn alignment_is_a_power_of_two() {
+        assert!(AllocationPolicy::Aligned(64).validate().is_ok());
+        assert!(AllocationPolicy::Aligned(1).validate().is_ok());
+        assert!(AllocationPolicy::Aligned(0).validate().is_err());
+        assert!(AllocationPolicy::Aligned(48).validate().is_err());
+    }
+
+    #[test]
+    fn mip_chain_counts_in_the_size() {
+        let key = PoolKey::new(PixelFormat::R16, 256, 256);
+        assert_eq!(key.levels(), 1);
+        assert_eq!(key.byte_size(), 2 * 256 * 256);
+        let key = key.with_mipmaps(true);
+        assert_eq!(key.levels(), 9);
+        // A third more than level 0, plus the 1x1 level
+        assert_eq!(key.byte_size(), 2 * (256 * 256 * 4 - 1) / 3);
+        let key = PoolKey::layered(PixelFormat::R16, 5, 2, 3).with_mipmaps(true);
+        assert_eq!(key.levels(), 3);
+        assert_eq!(key.byte_size(), 2 * (5 * 2 + 2 + 1) * 3);
+        assert_ne!(key, PoolKey::layered(PixelFormat::R16, 5, 2, 3));
+    }
+
+    #[test]
+    fn recycles_matching_textures() {
+        let key = PoolKey::new(PixelFormat::R16, 256, 256);
+        let mut pool = TexturePool::new(key.byte_size() * 2);
+        assert_eq!(pool.reserve(&key), Some(vec![]));
+        pool.put(key, 1);
+        assert_eq!(pool.take(&PoolKey::new(PixelFormat::R16, 512, 512)), None);
+        assert_eq!(
+            pool.take(&PoolKey::layered(PixelFormat::R16, 256, 256, 2)),
+            None
+        );
+        assert_eq!(pool.take(&key), Some(1));
+        assert_eq!(pool.take(&key), None);
+    }
+
+    #[test]
+    fn evicts_least_recently_released() {
+        let small = PoolKey::new(PixelFormat::R16, 256, 256);
+        let large = PoolKey::new(PixelFormat::R16, 256, 512);
+        let mut pool = TexturePool::new(small.byte_size() * 3);
+        for id in 1..=3 {
+            assert_eq!(pool.reserve(&small), Some(vec![]));
+            pool.put(small, id);
+        }
+        assert_eq!(pool.reserve(&large), Some(vec![1, 2]));
+        assert_eq!(pool.allocated(), small.byte_size() * 3);
+        assert_eq!(pool.take(&small), Some(3));
+    }
+
+    #[test]
+    fn refuses_textures_over_budget() {
+        let key = PoolKey::new(PixelFormat::R16, 256, 256);
+        let mut pool = TexturePool::new(key.byte_size());
+        assert_eq!(pool.reserve(&key), Some(vec![]));
+        // The only texture is still in use, so nothing can be evicted.
+        assert_eq!(pool.reserve(&key), None);
+        pool.put(key, 1);
+        assert_eq!(pool.reserve(&key), Some(vec![1]));
+    }
+}
@@ -0,0 +1,120 @@
+/// Placement of one tile of an image that is too large for a single texture.
+/// Offsets and sizes are in image texels, rows counted from the start of the image data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TileLayout {
+    /// The part of the image drawn by this tile.
+    pub region_offset: (usize, usize),
+    pub region_size: (usize, usize),
+    /// The part of the image stored in the tile texture. This is the region plus a
+    /// `BORDER` texel border shared with the neighbouring tiles, so neither linear nor
+    /// bicubic filtering has seams.
+    pub texture_offset: (usize, usize),
+    pub texture_size: (usize, usize),
+}
+
+/// Texels beyond the region on each side of a tile, bicubic interpolation reads two.
+pub const BORDER: usize = 2;
+
+/// Splits an image into tiles that each fit in a `max_texture_size` square texture.
+/// Images that already fit get a single tile without borders.
+pub fn tile_layout(image_size: (usize, usize), max_texture_size: usize) -> Vec<TileLayout> {
+    if image_size.0 <= max_texture_size && image_size.1 <= max_texture_size {
+        return vec![TileLayout {
+            region_offset: (0, 0),
+            region_size: image_size,
+            texture_offset: (0, 0),
+            texture_size: image_size,
+        }];
+    }
+    assert!(
+        max_texture_size > 2 * BORDER,
+        "Texture size too small for tiling"
+    );
+    // Leave room for the border on both sides of every tile.
+    let stride = max_texture_size - 2 * BORDER;
+    let columns = split_axis(image_size.0, stride);
+    let rows = split_axis(image_size.1, stride);
+    rows.iter()
+        .flat_map(|&(y, h, ty, th)| {
+            columns.iter().map(move |&(x, w, tx, tw)| TileLayout {
+                region_offset: (x, y),
+                region_size: (w, h),
+                texture_offset: (tx, ty),
+                texture_size: (tw, th),
+            })
+        })
+        .collect()
+}
+
+// Returns (region start, region length, texture start, texture length) along one axis.
+fn split_axis(length: usize, stride: usize) -> Vec<(usize, usize, usize, usize)> {
+    (0..length)
+        .step_by(stride)
+        .map(|start| {
+            let end = (start + stride).min(length);
+            let texture_start = start.saturating_sub(BORDER);
+            let texture_end = (end + BORDER).min(length);
+            (
+                start,
+                end - start,
+                texture_start,
+                texture_end - texture_start,
+            )
+        })
+        .collect()
+}
+
+/// Copies the `size` texels at `offset` out of an image that is `image_width` texels wide.
+pub fn extract_region<T: Copy>(
+    data: &[T],
+    image_width: usize,
+    offset: (usize, usize),
+    size: (usize, usize),
+) -> Vec<T> {
+    assert!(offset.0 + size.0 <= image_width);
+    let mut region = Vec::with_capacity(size.0 * size.1);
+    for line in data.chunks(image_width).skip(offset.1).take(size.1) {
+        region.extend_from_slice(&line[offset.0..offset.0 + size.0]);
+    }
+    assert!(region.len() == size.0 * size.1);
+    region
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_image_is_one_tile() {
+        let tiles = tile_layout((300, 200), 1024);
+        assert_eq!(tiles.len(), 1);
+        assert_eq!(tiles[0].texture_size, (300, 200));
+    }
+
+    #[test]
+    fn tiles_cover_image_with_borders() {
+        let max = 16;
+        let tiles = tile_layout((40, 20), max);
+        assert_eq!(tiles.len(), 4 * 2);
+        let covered: usize = tiles
+            .iter()
+            .map(|t| t.region_size.0 * t.region_size.1)
+            .sum();
+        assert_eq!(covered, 40 * 20);
+        for t in &tiles {
+            assert!(t.texture_size.0 <= max && t.texture_size.1 <= max);
+            // The texture holds the region and the border beyond it, except at the image edges.
+            assert_eq!(t.texture_offset.0, t.region_offset.0.saturating_sub(BORDER));
+            assert_eq!(
+                t.texture_offset.0 + t.texture_size.0,
+                (t.region_offset.0 + t.region_size.0 + BORDER).min(40)
+            );
+        }
+    }
+
+    #[test]
+    fn extracts_rows() {
+        let data: Vec<u16> = (0..12).collect();
+        assert_eq!(extract_region(&data, 4, (1, 1), (2, 2)), vec![5, 6, 9, 10]);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::error::{Error, Result};
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+/// Tracks the completion of one upload on the uploader thread.
+/// Clones share the same state, so the token can be passed on to whoever samples the texture.
+#[derive(Debug, Clone)]
+pub struct UploadToken {
+    inner: Arc<(Mutex<UploadState>, Condvar)>,
+}
+
+#[derive(Debug, Default)]
+struct UploadState {
+    // The GLsync inserted after the upload, owned (and deleted) by the uploader thread.
+    fence: Option<usize>,
+    complete: bool,
+    // Set when the upload failed on the uploader thread, the token is complete as well.
+    error: Option<String>,
+}
+
+impl UploadToken {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new((Mutex::new(UploadState::default()), Condvar::new())),
+        }
+    }
+
+    /// True once the GPU has finished writing the texture.
+    pub fn is_complete(&self) -> bool {
+        self.inner.0.lock().unwrap().complete
+    }
+
+    /// True if both tokens track the same upload, i.e. one is a clone of the other.
+    pub fn same_upload(&self, other: &UploadToken) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    /// Blocks until the upload is complete, returns the error if the upload failed.
+    pub fn wait(&self) -> Result<()> {
+        let (state, cvar) = &*self.inner;
+        let state = cvar
+            .wait_while(state.lock().unwrap(), |s| !s.complete)
+            .unwrap();
+        state.result()
+    }
+
+    /// Blocks until the upload is complete or the timeout expires, returns true if complete.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<bool> {
+        let (state, cvar) = &*self.inner;
+        let (state, _) = cvar
+            .wait_timeout_while(state.lock().unwrap(), timeout, |s| !s.complete)
+            .unwrap();
+        state.result()?;
+        Ok(state.complete)
+    }
+
+    pub(crate) fn set_fence(&self, fence: usize) {
+        let (state, cvar) = &*self.inner;
+        state.lock().unwrap().fence = Some(fence);
+        // Wake up anyone waiting for the fence to be submitted
+        cvar.notify_all();
+    }
+
+    /// The fence of a pending upload, `None` if complete or not yet submitted.
+    pub(crate) fn pending_fence(&self) -> Option<usize> {
+        let state = self.inner.0.lock().unwrap();
+        if state.complete {
+            None
+        } else {
+            state.fence
+        }
+    }
+
+    /// Runs `f` with the fence of a pending upload, first blocking until the uploader thread
+    /// has submitted it. The uploader can't delete the fence while `f` runs, so it is safe to
+    /// wait on it from another context in the share group. Returns `None` if already complete.
+    pub(crate) fn with_pending_fence<R>(&self, f: impl FnOnce(usize) -> R) -> Option<R> {
+        let (state, cvar) = &*self.inner;
+        let state = cvar
+            .wait_while(state.lock().unwrap(), |s| !s.complete && s.fence.is_none())
+            .unwrap();
+        if state.complete {
+            return None;
+        }
+        state.fence.map(f)
+    }
+
+    /// Marks the upload complete and wakes up any waiters.
+    /// Returns the fence, which the caller is responsible for deleting.
+    pub(crate) fn complete(&self) -> Option<usize> {
+        let (state, cvar) = &*self.inner;
+        let mut state = state.lock().unwrap();
+        state.complete = true;
+        cvar.notify_all();
+        state.fence.take()
+    }
+
+    /// Marks the upload as failed, waiters get the reason as an error.
+    /// Returns the fence, which the caller is responsible for deleting.
+    pub(crate) fn fail(&self, reason: String) -> Option<usize> {
+        let (state, cvar) = &*self.inner;
+        let mut state = state.lock().unwrap();
+        state.complete = true;
+        state.error = Some(reason);
+        cvar.notify_all();
+        state.fence.take()
+    }
+}
+
+impl UploadState {
+    fn result(&self) -> Result<()> {
+        match &self.error {
+            Some(reason) => Err(Error::Uploader(reason.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for UploadToken {
+    fn default() -> Self {
+        UploadToken::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn completes_across_threads() {
+        let token = UploadToken::new();
+        token.set_fence(42);
+        assert!(!token.is_complete());
+        assert_eq!(token.pending_fence(), Some(42));
+        assert!(!token.wait_timeout(Duration::from_millis(1)).unwrap());
+
+        let uploader = token.clone();
+        let handle = thread::spawn(move || uploader.complete());
+        token.wait().unwrap();
+        assert!(token.is_complete());
+        assert_eq!(token.pending_fence(), None);
+        assert_eq!(handle.join().unwrap(), Some(42));
+    }
+
+    #[test]
+    fn pending_fence_waits_for_submission() {
+        let token = UploadToken::new();
+        let uploader = token.clone();
+        let handle = thread::spawn(move || uploader.set_fence(7));
+        assert_eq!(token.with_pending_fence(|fence| fence), Some(7));
+        handle.join().unwrap();
+        token.complete();
+        assert_eq!(token.with_pending_fence(|fence| fence), None);
+    }
+
+    #[test]
+    fn failure_is_reported_to_waiters() {
+        let token = UploadToken::new();
+        token.set_fence(3);
+        assert_eq!(token.fail("out of memory".into()), Some(3));
+        assert!(token.is_complete());
+        assert!(token.wait().is_err());
+    }
+}